@@ -0,0 +1,11 @@
+ALTER TABLE monitor
+  ADD COLUMN IF NOT EXISTS league_rating double precision,
+  ADD COLUMN IF NOT EXISTS league_glicko double precision,
+  ADD COLUMN IF NOT EXISTS league_rd double precision,
+  ADD COLUMN IF NOT EXISTS league_rank text,
+  ADD COLUMN IF NOT EXISTS league_percentile double precision,
+  ADD COLUMN IF NOT EXISTS league_apm double precision,
+  ADD COLUMN IF NOT EXISTS league_pps double precision,
+  ADD COLUMN IF NOT EXISTS league_vs double precision,
+  ADD COLUMN IF NOT EXISTS league_standing integer,
+  ADD COLUMN IF NOT EXISTS last_notified_rating double precision;
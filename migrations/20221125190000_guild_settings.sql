@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS guild_settings (
+  guild_id text NOT NULL,
+  prefix text,
+  PRIMARY KEY(guild_id)
+);
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS user_snapshot (
+  id bigserial NOT NULL,
+  user_id char(24) NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  league_rating double precision,
+  league_rank text,
+  personal_best_40l integer,
+  personal_best_blitz integer,
+  game_time double precision NOT NULL,
+  games_played integer NOT NULL,
+  games_won integer NOT NULL,
+  xp double precision NOT NULL,
+  PRIMARY KEY(id)
+);
+
+CREATE INDEX IF NOT EXISTS user_snapshot_user_id_created_at_idx
+  ON user_snapshot (user_id, created_at);
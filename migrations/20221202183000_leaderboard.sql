@@ -0,0 +1,13 @@
+ALTER TABLE monitor
+  ADD COLUMN IF NOT EXISTS country text,
+  ADD COLUMN IF NOT EXISTS games_won integer NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS leaderboard_entry (
+  scope text NOT NULL,
+  metric text NOT NULL,
+  user_id char(24) NOT NULL,
+  position integer NOT NULL,
+  value double precision NOT NULL,
+  posted_at timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY(scope, metric, user_id)
+);
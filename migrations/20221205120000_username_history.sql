@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS username_history (
+  id bigserial NOT NULL,
+  user_id char(24) NOT NULL,
+  old_username text NOT NULL,
+  new_username text NOT NULL,
+  changed_at timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY(id)
+);
+
+CREATE INDEX IF NOT EXISTS username_history_user_id_idx
+  ON username_history (user_id);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS discord_link (
+  discord_id text NOT NULL,
+  user_id char(24) NOT NULL UNIQUE,
+  linked_at timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY(discord_id)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS rank_role (
+  guild_id text NOT NULL,
+  rank text NOT NULL,
+  role_id text NOT NULL,
+  PRIMARY KEY(guild_id, rank)
+);
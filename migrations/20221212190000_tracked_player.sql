@@ -0,0 +1,55 @@
+-- Split monitor into one row of stats per tracked player and one
+-- subscription per channel following the player
+CREATE TABLE IF NOT EXISTS tracked_player (
+  user_id char(24) NOT NULL,
+  username text NOT NULL,
+  country text,
+  last_match_id char(24),
+  game_time double precision NOT NULL,
+  games_played integer NOT NULL,
+  games_won integer NOT NULL DEFAULT 0,
+  last_personal_best_blitz integer,
+  last_personal_best_40l integer,
+  league_rating double precision,
+  league_glicko double precision,
+  league_rd double precision,
+  league_rank text,
+  league_percentile double precision,
+  league_apm double precision,
+  league_pps double precision,
+  league_vs double precision,
+  league_standing integer,
+  last_notified_rating double precision,
+  PRIMARY KEY(user_id)
+);
+
+CREATE TABLE IF NOT EXISTS subscription (
+  channel_id text NOT NULL,
+  -- Unknown for subscriptions migrated from monitor until the next post
+  guild_id text,
+  user_id char(24) NOT NULL REFERENCES tracked_player (user_id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY(channel_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS subscription_user_id_idx ON subscription (user_id);
+CREATE INDEX IF NOT EXISTS subscription_guild_id_idx ON subscription (guild_id);
+
+-- Keep the most played state of players monitored in several channels
+INSERT INTO tracked_player (user_id, username, country, last_match_id, game_time, games_played,
+    games_won, last_personal_best_blitz, last_personal_best_40l, league_rating, league_glicko,
+    league_rd, league_rank, league_percentile, league_apm, league_pps, league_vs,
+    league_standing, last_notified_rating)
+SELECT DISTINCT ON (user_id) user_id, username, country, last_match_id, game_time, games_played,
+    games_won, last_personal_best_blitz, last_personal_best_40l, league_rating, league_glicko,
+    league_rd, league_rank, league_percentile, league_apm, league_pps, league_vs,
+    league_standing, last_notified_rating
+FROM monitor
+ORDER BY user_id, games_played DESC, game_time DESC
+ON CONFLICT (user_id) DO NOTHING;
+
+INSERT INTO subscription (channel_id, user_id)
+SELECT channel_id, user_id FROM monitor
+ON CONFLICT (channel_id, user_id) DO NOTHING;
+
+DROP TABLE monitor;
@@ -0,0 +1,9 @@
+-- Events posted for the players followed by a channel, every event if missing
+CREATE TABLE IF NOT EXISTS channel_notify (
+  channel_id text NOT NULL,
+  events text[] NOT NULL,
+  PRIMARY KEY(channel_id)
+);
+
+-- Events posted for one player, overriding the channel's when not null
+ALTER TABLE subscription ADD COLUMN IF NOT EXISTS events text[];
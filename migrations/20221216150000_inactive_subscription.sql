@@ -0,0 +1,4 @@
+-- Subscriptions of channels the bot cannot see anymore are kept but not
+-- posted to until the channel is reachable again
+ALTER TABLE subscription ADD COLUMN IF NOT EXISTS inactive_since timestamptz;
+ALTER TABLE subscription ADD COLUMN IF NOT EXISTS inactive_reason text;
@@ -0,0 +1,12 @@
+-- Discord account connected to the tetr.io account, as of the last refresh
+ALTER TABLE tracked_player ADD COLUMN IF NOT EXISTS discord_id text;
+
+-- Rank role last given to a player's Discord account in a guild, so members
+-- are only updated when their rank or account changes
+CREATE TABLE IF NOT EXISTS rank_role_sync (
+  guild_id text NOT NULL,
+  user_id char(24) NOT NULL,
+  discord_id text NOT NULL,
+  role_id text,
+  PRIMARY KEY(guild_id, user_id)
+);
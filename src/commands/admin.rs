@@ -46,7 +46,7 @@ pub async fn dblatency(ctx: Context<'_>) -> Result<(), Error> {
     let pool = &ctx.data().db_pool;
     let rows = sqlx::query!(
         r#"
-    SELECT * FROM monitor WHERE channel_id = $1"#,
+    SELECT * FROM subscription WHERE channel_id = $1"#,
         ctx.channel_id().to_string()
     )
     .fetch_all(pool)
@@ -65,3 +65,32 @@ pub async fn dblatency(ctx: Context<'_>) -> Result<(), Error> {
     .await?;
     Ok(())
 }
+
+/// Show tetr.io response cache statistics
+#[poise::command(prefix_command, owners_only, hide_in_help)]
+pub async fn tetrcache(ctx: Context<'_>) -> Result<(), Error> {
+    let stats = ctx.data().tetr.cache_stats();
+    let total = stats.hits + stats.misses;
+    ctx.send(|b| {
+        b.embed(|b| {
+            b.title("tetr.io cache")
+                .field("Hits", stats.hits, true)
+                .field("Misses", stats.misses, true)
+                .field("Entries", stats.entries, true)
+                .field(
+                    "Hit rate",
+                    format!(
+                        "{:.1}%",
+                        if total == 0 {
+                            0.0
+                        } else {
+                            stats.hits as f64 / total as f64 * 100.0
+                        }
+                    ),
+                    true,
+                )
+        })
+    })
+    .await?;
+    Ok(())
+}
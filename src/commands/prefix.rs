@@ -0,0 +1,108 @@
+use crate::{CommandResult, Context, Data, Error};
+
+use poise::serenity_prelude::GuildId;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+mod db;
+
+const MAX_PREFIX_LEN: usize = 16;
+
+/// Custom prefixes of guilds that have been seen, `None` if a guild uses the
+/// default prefix
+pub type PrefixCache = Arc<RwLock<HashMap<GuildId, Option<String>>>>;
+
+async fn get_guild_prefix(data: &Data, guild_id: GuildId) -> Result<Option<String>, Error> {
+    if let Some(prefix) = data.prefix_cache.read().unwrap().get(&guild_id) {
+        return Ok(prefix.clone());
+    }
+    let prefix = db::get_guild_prefix(&data.db_pool, &guild_id.to_string()).await?;
+    data.prefix_cache
+        .write()
+        .unwrap()
+        .insert(guild_id, prefix.clone());
+    Ok(prefix)
+}
+
+async fn set_guild_prefix(data: &Data, guild_id: GuildId, prefix: Option<String>) -> CommandResult {
+    db::set_guild_prefix(&data.db_pool, &guild_id.to_string(), prefix.as_deref()).await?;
+    data.prefix_cache.write().unwrap().insert(guild_id, prefix);
+    Ok(())
+}
+
+/// Prefix of the guild the message was sent in, or the configured default
+pub async fn dynamic_prefix(
+    ctx: poise::PartialContext<'_, Data, Error>,
+) -> Result<Option<String>, Error> {
+    let prefix = match ctx.guild_id {
+        Some(guild_id) => get_guild_prefix(ctx.data, guild_id).await?,
+        None => None,
+    };
+    Ok(Some(
+        prefix.unwrap_or_else(|| ctx.data.config.prefix.clone()),
+    ))
+}
+
+/// Show the command prefix of this server
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("set", "reset"),
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn prefix(ctx: Context<'_>) -> CommandResult {
+    let prefix = match ctx.guild_id() {
+        Some(guild_id) => get_guild_prefix(ctx.data(), guild_id).await?,
+        None => None,
+    };
+    ctx.say(format!(
+        "Prefix is `{}`",
+        prefix.as_deref().unwrap_or(&ctx.data().config.prefix)
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Set the command prefix of this server
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn set(ctx: Context<'_>, #[description = "New prefix"] prefix: String) -> CommandResult {
+    if prefix.is_empty() || prefix.len() > MAX_PREFIX_LEN || prefix.contains(char::is_whitespace) {
+        ctx.say(format!(
+            "Prefix must be 1 to {} characters without spaces",
+            MAX_PREFIX_LEN
+        ))
+        .await?;
+        return Ok(());
+    }
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("not in a guild"))?;
+    set_guild_prefix(ctx.data(), guild_id, Some(prefix.clone())).await?;
+    ctx.say(format!("Prefix set to `{}`", prefix)).await?;
+    Ok(())
+}
+
+/// Reset the command prefix of this server to the default
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn reset(ctx: Context<'_>) -> CommandResult {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("not in a guild"))?;
+    set_guild_prefix(ctx.data(), guild_id, None).await?;
+    ctx.say(format!("Prefix reset to `{}`", ctx.data().config.prefix))
+        .await?;
+    Ok(())
+}
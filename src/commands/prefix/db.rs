@@ -0,0 +1,33 @@
+use crate::{DbPool, Error};
+use anyhow::Context;
+
+pub async fn get_guild_prefix(pool: &DbPool, guild_id: &str) -> Result<Option<String>, Error> {
+    Ok(sqlx::query!(
+        r#"
+SELECT prefix FROM guild_settings WHERE guild_id = $1"#,
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to get guild prefix")?
+    .and_then(|r| r.prefix))
+}
+
+pub async fn set_guild_prefix(
+    pool: &DbPool,
+    guild_id: &str,
+    prefix: Option<&str>,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+INSERT INTO guild_settings (guild_id, prefix)
+VALUES ($1, $2)
+ON CONFLICT (guild_id) DO UPDATE SET prefix = EXCLUDED.prefix"#,
+        guild_id,
+        prefix
+    )
+    .execute(pool)
+    .await
+    .context("failed to set guild prefix")?;
+    Ok(())
+}
@@ -1,27 +1,63 @@
 use crate::{CommandResult, Context, Error};
-use db::Monitor;
+use db::TrackedPlayer;
 
 use anyhow::Context as anyhowContext;
 use country_emoji::code_to_flag;
 use futures::{stream, StreamExt};
-use poise::serenity_prelude::CreateEmbed;
+use poise::serenity_prelude::{self as serenity, CreateEmbed};
 use pretty_duration::pretty_duration;
 use std::time::Duration;
 
+mod chart;
+mod cleanup;
 mod client;
+mod compare;
 mod db;
+mod history;
+mod leaderboard;
+mod link;
+mod list;
+mod notify;
+mod poller;
+mod roles;
+mod transfer;
 
-const MAX_CONCURRENT_REQUESTS: usize = 64;
+use compare::compare;
+use history::history;
+use leaderboard::leaderboard;
+use link::{link, unlink};
+use list::list;
+use notify::notify;
+use roles::roles;
+use transfer::{export, import};
 
-fn format_tetr_user<'a>(user: &client::TetrUser, b: &'a mut CreateEmbed) -> &'a mut CreateEmbed {
-    let join_time = user
-        .ts
-        .as_ref()
-        .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+pub use cleanup::{inactive, on_event, spawn_sweeper};
+pub use client::{HttpTransport, TetrClient};
+pub use poller::{spawn_poller, spawn_snapshot_pruner, RefreshLocks};
+
+fn format_40l_time(ms: i32) -> String {
+    format!("{:.3}s", ms as f64 / 1000.0)
+}
+
+fn format_time_ago(ts: &str) -> Option<String> {
+    chrono::DateTime::parse_from_rfc3339(ts)
+        .ok()
         .map(|t| chrono::Utc::now() - t.with_timezone(&chrono::Utc))
-        .and_then(|d| d.to_std().ok());
-    if let Some(d) = join_time {
-        b.description(format!("Joined {}", timeago::Formatter::new().convert(d)));
+        .and_then(|d| d.to_std().ok())
+        .map(|d| timeago::Formatter::new().convert(d))
+}
+
+fn format_user_title(user: &client::TetrUser) -> String {
+    format!(
+        "{} {}",
+        &user.username,
+        code_to_flag(user.country.as_deref().unwrap_or_default()).unwrap_or_default()
+    )
+}
+
+fn format_tetr_user<'a>(user: &client::TetrUser, b: &'a mut CreateEmbed) -> &'a mut CreateEmbed {
+    if let Some(join_time) = user.ts.as_deref().and_then(format_time_ago) {
+        b.description(format!("Joined {}", join_time));
     }
     b.field(
         "Play time",
@@ -33,6 +69,47 @@ fn format_tetr_user<'a>(user: &client::TetrUser, b: &'a mut CreateEmbed) -> &'a
     .thumbnail(client::get_user_avatar_url(&user._id))
 }
 
+/// Status code of a failed Discord request
+fn http_status(e: &serenity::Error) -> Option<u16> {
+    match e {
+        serenity::Error::Http(e) => match e.as_ref() {
+            serenity::HttpError::UnsuccessfulRequest(res) => Some(res.status_code.as_u16()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Characters allowed in an embed description
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+/// Characters allowed in an embed field value
+const EMBED_FIELD_LIMIT: usize = 1024;
+
+/// Joins lines for an embed, keeping at most `max_lines` of them and
+/// `max_chars` characters in total, and ends with how many were left out.
+/// Embeds over Discord's limits are rejected, so long lists must be cut short.
+fn join_lines(lines: &[String], max_lines: usize, max_chars: usize) -> String {
+    // Room for the line counting the left out lines
+    const MORE_LINE_CHARS: usize = 32;
+    let mut kept = vec![];
+    let mut chars = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let line_chars = line.chars().count() + 1;
+        let reserved = if i + 1 < lines.len() {
+            MORE_LINE_CHARS
+        } else {
+            0
+        };
+        if i == max_lines || chars + line_chars + reserved > max_chars {
+            kept.push(format!("…and {} more", lines.len() - i));
+            break;
+        }
+        kept.push(line.clone());
+        chars += line_chars;
+    }
+    kept.join("\n")
+}
+
 fn append_latency(b: &mut CreateEmbed, l: Duration) -> &mut CreateEmbed {
     b.footer(|b| {
         b.text(format!(
@@ -47,7 +124,24 @@ fn append_latency(b: &mut CreateEmbed, l: Duration) -> &mut CreateEmbed {
 #[poise::command(
     prefix_command,
     slash_command,
-    subcommands("list", "monitor", "test", "remove", "record", "refresh", "monitor2"),
+    subcommands(
+        "list",
+        "monitor",
+        "test",
+        "remove",
+        "record",
+        "refresh",
+        "monitor2",
+        "history",
+        "leaderboard",
+        "compare",
+        "link",
+        "unlink",
+        "roles",
+        "export",
+        "import",
+        "notify"
+    ),
     guild_cooldown = 5
 )]
 pub async fn tetr(ctx: Context<'_>) -> CommandResult {
@@ -55,58 +149,78 @@ pub async fn tetr(ctx: Context<'_>) -> CommandResult {
     Ok(())
 }
 
-/// List tetr.io monitored users
-#[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
-pub async fn list(ctx: Context<'_>) -> CommandResult {
-    let monitors = db::get_monitors_for_channel(&ctx.data().db_pool, ctx.channel_id().to_string())
-        .await
-        .context("failed to add user")?;
-
-    if monitors.is_empty() {
-        ctx.say("No monitored users").await?;
-    } else {
-        ctx.say(
-            monitors
-                .iter()
-                .map(|m| m.username.as_str())
-                .collect::<Vec<_>>()
-                .join("\n"),
-        )
-        .await?;
-    }
-    Ok(())
+fn set_league_standing(p: &mut TrackedPlayer, league: &client::TetraLeagueStanding) {
+    p.league_rating = league.is_ranked().then_some(league.rating);
+    p.league_glicko = league.glicko;
+    p.league_rd = league.rd;
+    p.league_rank = Some(league.rank.clone());
+    p.league_percentile = league.percentile;
+    p.league_apm = league.apm;
+    p.league_pps = league.pps;
+    p.league_vs = league.vs;
+    p.league_standing = league.standing;
 }
 
-async fn fetch_new_monitor(ctx: &Context<'_>, user: String) -> Result<Monitor, Error> {
-    let user_data = client::get_user(ctx, &user).await?;
-    let m = Monitor {
-        channel_id: ctx.channel_id().to_string(),
-        user_id: user_data._id.clone(),
-        username: user_data.username.clone(),
-        game_time: user_data.gametime,
-        games_played: user_data.gamesplayed,
+fn new_player(user: &client::TetrUser, record: &client::TetrUserRecord) -> TrackedPlayer {
+    let mut p = TrackedPlayer {
+        user_id: user._id.clone(),
+        username: user.username.clone(),
+        game_time: user.gametime,
+        games_played: user.gamesplayed,
+        games_won: user.gameswon,
+        country: user.country.clone(),
         last_match_id: None,
-        last_personal_best_40l: None,
-        last_personal_best_blitz: None,
+        last_personal_best_40l: record.best_40l_ms(),
+        last_personal_best_blitz: record.best_blitz_score(),
+        league_rating: None,
+        league_glicko: None,
+        league_rd: None,
+        league_rank: None,
+        league_percentile: None,
+        league_apm: None,
+        league_pps: None,
+        league_vs: None,
+        league_standing: None,
+        last_notified_rating: None,
+        discord_id: user.connections.discord.as_ref().map(|d| d.id.clone()),
     };
-    Ok(m)
+    set_league_standing(&mut p, &user.league);
+    p.last_notified_rating = p.league_rating;
+    p
 }
 
-/// Monitor tetr.io users
-#[poise::command(prefix_command, slash_command, owners_only, guild_cooldown = 5)]
-pub async fn monitor2(
-    ctx: Context<'_>,
-    #[description = "Tetr usernames/ids to monitor"] users: Vec<String>,
-) -> CommandResult {
+async fn fetch_new_player(ctx: &Context<'_>, user: String) -> Result<TrackedPlayer, Error> {
+    let data = ctx.data();
+    let (user_data, record) =
+        tokio::try_join!(data.tetr.get_user(&user), data.tetr.get_user_record(&user))?;
+    Ok(new_player(&user_data, &record))
+}
+
+/// Subscribes the current channel to a player
+async fn subscribe(ctx: &Context<'_>, player: &TrackedPlayer) -> Result<(), db::DbError> {
+    db::subscribe(
+        &ctx.data().db_pool,
+        player,
+        &ctx.channel_id().to_string(),
+        ctx.guild_id().map(|g| g.to_string()).as_deref(),
+    )
+    .await
+}
+
+const MAX_LISTED_FAILED_USERS: usize = 40;
+
+/// Fetches and monitors many users in the current channel concurrently,
+/// reporting how many were added
+async fn monitor_users(ctx: &Context<'_>, users: Vec<String>) -> CommandResult {
     let reply_handle = ctx.say("Fetching users from tetr.io").await?;
     let start = std::time::Instant::now();
+    let (users, mut failed_users): (Vec<_>, Vec<_>) =
+        users.into_iter().partition(|u| client::is_valid_user(u));
     let monitors = stream::iter(users)
-        .map(|u| async { (fetch_new_monitor(&ctx, u.clone()).await, u) })
-        .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+        .map(|u| async { (fetch_new_player(ctx, u.clone()).await, u) })
+        .buffer_unordered(ctx.data().config.tetr.max_concurrent_requests)
         .collect::<Vec<_>>()
         .await;
-    dbg!(start.elapsed());
-    let mut failed_users = vec![];
     let mut fetched_users = vec![];
     for (m, u) in monitors.iter() {
         match m {
@@ -120,8 +234,8 @@ pub async fn monitor2(
 
     let mut num_inserted = 0;
     let mut num_duplicate = 0;
-    for m in fetched_users {
-        match db::insert_monitor(&ctx.data().db_pool, m).await {
+    for p in fetched_users {
+        match subscribe(ctx, p).await {
             Ok(_) => {
                 num_inserted += 1;
             }
@@ -130,8 +244,8 @@ pub async fn monitor2(
                     num_duplicate += 1;
                 }
                 db::DbError::Internal(_) => {
-                    println!("failed to insert monitor to db {:?}", e);
-                    failed_users.push(m.username.clone())
+                    println!("failed to insert subscription to db {:?}", e);
+                    failed_users.push(p.username.clone())
                 }
             },
         }
@@ -139,12 +253,16 @@ pub async fn monitor2(
 
     let latency = start.elapsed();
     reply_handle
-        .edit(ctx, |b| {
+        .edit(*ctx, |b| {
             b.embed(|b| {
                 b.title(format!("Monitored {} new users", num_inserted));
                 append_latency(b, latency);
                 if !failed_users.is_empty() {
-                    b.field("Failed users", failed_users.join("\n"), true);
+                    b.field(
+                        "Failed users",
+                        join_lines(&failed_users, MAX_LISTED_FAILED_USERS, EMBED_FIELD_LIMIT),
+                        true,
+                    );
                 }
                 if num_duplicate != 0 {
                     b.field("Duplicate users", num_duplicate, true);
@@ -158,36 +276,31 @@ pub async fn monitor2(
     Ok(())
 }
 
+/// Monitor tetr.io users
+#[poise::command(prefix_command, slash_command, owners_only, guild_cooldown = 5)]
+pub async fn monitor2(
+    ctx: Context<'_>,
+    #[description = "Tetr usernames/ids to monitor"] users: Vec<String>,
+) -> CommandResult {
+    monitor_users(&ctx, users).await
+}
+
 /// Monitor a tetr.io user
 #[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
 pub async fn monitor(
     ctx: Context<'_>,
-    #[description = "Tetr username/id to monitor"] user: String,
+    #[description = "Tetr username/id, @mention or me to monitor"] user: String,
 ) -> CommandResult {
-    let user_data = client::get_user(&ctx, &user).await?;
-
-    // Create new monitor
-    let m = Monitor {
-        channel_id: ctx.channel_id().to_string(),
-        user_id: user_data._id.clone(),
-        username: user_data.username.clone(),
-        game_time: user_data.gametime,
-        games_played: user_data.gamesplayed,
-        last_match_id: None,
-        last_personal_best_40l: None,
-        last_personal_best_blitz: None,
-    };
+    let user = link::resolve_user(&ctx, Some(&user)).await?;
+    let data = ctx.data();
+    let (user_data, record) =
+        tokio::try_join!(data.tetr.get_user(&user), data.tetr.get_user_record(&user))?;
 
-    match db::insert_monitor(&ctx.data().db_pool, &m).await {
+    match subscribe(&ctx, &new_player(&user_data, &record)).await {
         Ok(_) => {
             ctx.send(|b| {
                 b.embed(|b| {
-                    b.title(format!(
-                        "Saved {} {}",
-                        &user_data.username,
-                        code_to_flag(user_data.country.as_deref().unwrap_or_default())
-                            .unwrap_or_default()
-                    ));
+                    b.title(format!("Saved {}", format_user_title(&user_data)));
                     format_tetr_user(&user_data, b)
                 })
             })
@@ -206,25 +319,53 @@ pub async fn monitor(
                 .await?;
             }
             db::DbError::Internal(_) => {
-                Err(e).context("failed to insert monitor into DB")?;
+                Err(e).context("failed to insert subscription into DB")?;
             }
         },
     };
     Ok(())
 }
 
+/// Max choices Discord shows for autocomplete
+const MAX_AUTOCOMPLETE_CHOICES: i64 = 25;
+
+/// Suggests usernames monitored in the current channel
+async fn autocomplete_monitored_user(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    db::search_monitored_usernames(
+        &ctx.data().db_pool,
+        &ctx.channel_id().to_string(),
+        partial,
+        MAX_AUTOCOMPLETE_CHOICES,
+    )
+    .await
+    .unwrap_or_else(|why| {
+        println!("error during username autocomplete: {:?}", why);
+        vec![]
+    })
+}
+
 /// Remove a tetr.io user from the monitor list
 #[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
 pub async fn remove(
     ctx: Context<'_>,
-    #[description = "Tetr username to remove"] user: String,
+    #[description = "Tetr username/id to remove"]
+    #[autocomplete = "autocomplete_monitored_user"]
+    user: String,
 ) -> CommandResult {
-    let m = db::delete_monitor(&ctx.data().db_pool, &ctx.channel_id().to_string(), &user).await?;
+    let p = match db::unsubscribe(&ctx.data().db_pool, &ctx.channel_id().to_string(), &user).await?
+    {
+        Some(p) => p,
+        None => {
+            ctx.say(format!("{} is not monitored in this channel", user))
+                .await?;
+            return Ok(());
+        }
+    };
     ctx.send(|b| {
         b.embed(|b| {
-            b.title(format!("{} removed from the list", user))
-                .description(&m.user_id)
-                .thumbnail(client::get_user_avatar_url(&m.user_id))
+            b.title(format!("{} removed from the list", p.username))
+                .description(&p.user_id)
+                .thumbnail(client::get_user_avatar_url(&p.user_id))
                 .footer(|b| b.text("By Vieri Corp.™ All Rights Reserved"))
         })
     })
@@ -246,21 +387,76 @@ pub async fn test(ctx: Context<'_>, #[description = "int test"] id: i32) -> Comm
     Ok(())
 }
 
+fn format_record(record: &client::TetrRecord, rank: Option<i32>, result: String) -> String {
+    let ec = &record.endcontext;
+    let mut lines = vec![format!("**{}**", result)];
+    if let Some(rank) = rank {
+        lines.push(format!("#{} global", rank));
+    }
+    lines.push(format!(
+        "{:.2} PPS, {:.2} KPP, {} finesse faults",
+        ec.pps(),
+        ec.kpp(),
+        ec.finesse_faults()
+    ));
+    if let Some(set) = format_time_ago(&record.ts) {
+        lines.push(format!("Set {}", set));
+    }
+    lines.push(format!(
+        "[Replay]({})",
+        client::get_replay_url(&record.replayid)
+    ));
+    lines.join("\n")
+}
+
+/// Show a tetr.io user's personal records
 #[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
 pub async fn record(
     ctx: Context<'_>,
-    #[description = "Tetr username to get record"] user: String,
+    #[description = "Tetr username/id or @mention, yourself by default"]
+    #[autocomplete = "autocomplete_monitored_user"]
+    user: Option<String>,
 ) -> CommandResult {
-    let record = client::get_user_record(&ctx, &user).await?;
-    println!("{:#?}", record);
-    Ok(())
-}
+    let user = link::resolve_user(&ctx, user.as_deref()).await?;
+    let data = ctx.data();
+    let start = std::time::Instant::now();
+    let (user_data, record) =
+        tokio::try_join!(data.tetr.get_user(&user), data.tetr.get_user_record(&user))?;
+    let latency = start.elapsed();
 
-async fn refresh_single(ctx: &Context<'_>, m: &Monitor) -> Result<(), Error> {
-    let record = client::get_user_record(ctx, &m.user_id)
-        .await
-        .context("failed to get user record")?;
-    println!("{:#?}", record);
+    let sprint = &record.records._40l;
+    let blitz = &record.records.blitz;
+    ctx.send(|b| {
+        b.embed(|b| {
+            b.title(format!("Records of {}", format_user_title(&user_data)))
+                .thumbnail(client::get_user_avatar_url(&user_data._id))
+                .field(
+                    "40 Lines",
+                    sprint.record.as_ref().map_or("No record".into(), |r| {
+                        format_record(
+                            r,
+                            sprint.rank,
+                            format_40l_time(r.endcontext.final_time.round() as i32),
+                        )
+                    }),
+                    true,
+                )
+                .field(
+                    "Blitz",
+                    blitz.record.as_ref().map_or("No record".into(), |r| {
+                        format_record(r, blitz.rank, r.endcontext.score.to_string())
+                    }),
+                    true,
+                )
+                .field(
+                    "Zen",
+                    format!("Level {}\nScore {}", record.zen.level, record.zen.score),
+                    true,
+                );
+            append_latency(b, latency)
+        })
+    })
+    .await?;
     Ok(())
 }
 
@@ -268,21 +464,26 @@ async fn refresh_single(ctx: &Context<'_>, m: &Monitor) -> Result<(), Error> {
 pub async fn refresh(ctx: Context<'_>) -> CommandResult {
     let reply_handle = ctx.say("Refreshing").await?;
     let start = std::time::Instant::now();
-    let monitors = db::get_monitors_for_channel(&ctx.data().db_pool, ctx.channel_id().to_string())
+    let data = ctx.data();
+    let players = db::get_players_for_channel(&data.db_pool, ctx.channel_id().to_string())
         .await
         .context("failed to get monitored users from db")?;
-    let results = stream::iter(monitors)
-        .map(|m| async { (refresh_single(&ctx, &m).await, m) })
-        .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+    // Updates are posted to every channel following a player, not just this one
+    let results = stream::iter(players)
+        .map(|p| async {
+            let result = match db::get_subscriptions_for_player(&data.db_pool, &p.user_id).await {
+                Ok(subs) => poller::refresh_player(&ctx.discord().http, data, &p, &subs).await,
+                Err(why) => Err(why),
+            };
+            (result, p)
+        })
+        .buffer_unordered(data.config.tetr.max_concurrent_requests)
         .collect::<Vec<_>>()
         .await;
     let mut num_errors = 0;
-    for (result, m) in results.iter() {
+    for (result, p) in results.iter() {
         if let Err(why) = result {
-            println!(
-                "error during refresh for {} {}: {:?}",
-                m.channel_id, m.user_id, why
-            );
+            println!("error during refresh for {}: {:?}", p.user_id, why);
             num_errors += 1;
         }
     }
@@ -311,3 +512,24 @@ pub async fn refresh(ctx: Context<'_>) -> CommandResult {
         .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_lines_within_limits() {
+        let lines = (1..=5).map(|i| format!("line {}", i)).collect::<Vec<_>>();
+        assert_eq!(
+            join_lines(&lines, 10, 1000),
+            "line 1\nline 2\nline 3\nline 4\nline 5"
+        );
+        assert_eq!(join_lines(&lines, 2, 1000), "line 1\nline 2\n…and 3 more");
+
+        let long = vec!["x".repeat(600), "y".repeat(600), "z".into()];
+        let joined = join_lines(&long, 10, 1024);
+        assert!(joined.chars().count() <= 1024);
+        assert!(joined.ends_with("…and 2 more"));
+        assert_eq!(join_lines(&[], 10, 1024), "");
+    }
+}
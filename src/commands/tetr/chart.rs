@@ -0,0 +1,101 @@
+use crate::Error;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use plotters::prelude::*;
+use plotters::style::{register_font, FontStyle};
+use std::{io::Cursor, sync::Once};
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 450;
+const FONT: &str = "sans-serif";
+
+static REGISTER_FONT: Once = Once::new();
+
+/// Bundled so charts render without any system fonts installed
+fn register_fonts() {
+    REGISTER_FONT.call_once(|| {
+        let font = include_bytes!("../../../assets/fonts/DejaVuSans.ttf");
+        if register_font(FONT, FontStyle::Normal, font).is_err() {
+            println!("failed to register chart font");
+        }
+    });
+}
+
+/// Renders `points` (sorted by time) as a PNG line chart
+pub fn render_line_chart(
+    title: &str,
+    y_label: &str,
+    points: &[(DateTime<Utc>, f64)],
+) -> Result<Vec<u8>, Error> {
+    register_fonts();
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (first.0, last.0),
+        _ => anyhow::bail!("no points to render"),
+    };
+    let (mut y_min, mut y_max) = points
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), (_, y)| {
+            (min.min(*y), max.max(*y))
+        });
+    // Leave some room above and below the line, even if it is flat
+    let margin = ((y_max - y_min) * 0.1).max(y_max.abs() * 0.01).max(1.0);
+    y_min -= margin;
+    y_max += margin;
+    let span = (last - first).num_days();
+
+    let mut buffer = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, (FONT, 24))
+            .margin(12)
+            .x_label_area_size(36)
+            .y_label_area_size(64)
+            .build_cartesian_2d(first..last, y_min..y_max)?;
+        chart
+            .configure_mesh()
+            .label_style((FONT, 14))
+            .y_desc(y_label)
+            .x_labels(6)
+            .x_label_formatter(&|t| {
+                if span >= 2 {
+                    t.format("%b %d").to_string()
+                } else {
+                    t.format("%H:%M").to_string()
+                }
+            })
+            .draw()?;
+        chart.draw_series(LineSeries::new(
+            points.iter().copied(),
+            ShapeStyle::from(&BLUE).stroke_width(2),
+        ))?;
+        root.present()?;
+    }
+
+    let image = image::RgbImage::from_raw(WIDTH, HEIGHT, buffer)
+        .context("chart buffer has the wrong size")?;
+    let mut png = Cursor::new(vec![]);
+    image
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .context("failed to encode chart")?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_png() {
+        let now = Utc::now();
+        let points = [
+            (now - chrono::Duration::days(2), 15000.0),
+            (now - chrono::Duration::days(1), 15250.5),
+            (now, 15100.0),
+        ];
+        let png = render_line_chart("osk TR", "TR", &points).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+}
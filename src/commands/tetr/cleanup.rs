@@ -0,0 +1,178 @@
+use super::{db, format_time_ago, http_status, join_lines, EMBED_DESCRIPTION_LIMIT};
+use crate::{CommandResult, Context, Data, Error};
+
+use anyhow::Context as anyhowContext;
+use poise::serenity_prelude::{Channel, ChannelId, Http};
+use std::sync::Arc;
+
+const MAX_LISTED_CHANNELS: usize = 25;
+
+/// Deletes the subscriptions of channels that were deleted and of guilds the
+/// bot was removed from
+pub async fn on_event(event: &poise::Event<'_>, data: &Data) -> Result<(), Error> {
+    match event {
+        poise::Event::ChannelDelete { channel } => {
+            let deleted =
+                db::delete_channel_subscriptions(&data.db_pool, &channel.id.to_string()).await?;
+            if deleted > 0 {
+                println!(
+                    "Deleted {} subscriptions of deleted channel {}",
+                    deleted, channel.id
+                );
+            }
+        }
+        // An unavailable guild is an outage, the bot is still in it
+        poise::Event::GuildDelete { incomplete, full } if !incomplete.unavailable => {
+            // Subscriptions may not know their guild yet, the cached guild
+            // knows its channels
+            let channel_ids = full
+                .iter()
+                .flat_map(|g| g.channels.keys())
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+            let deleted = db::delete_guild_subscriptions(
+                &data.db_pool,
+                &incomplete.id.to_string(),
+                &channel_ids,
+            )
+            .await?;
+            println!(
+                "Left guild {}, deleted {} subscriptions",
+                incomplete.id, deleted
+            );
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Why the bot cannot see a channel, `None` if it can. Fills in the guild of
+/// its subscriptions when the bot can see it.
+async fn check_channel(
+    http: &Http,
+    data: &Data,
+    channel_id: &str,
+) -> Result<Option<&'static str>, Error> {
+    let id = match channel_id.parse() {
+        Ok(id) => ChannelId(id),
+        Err(_) => return Ok(Some("invalid channel id")),
+    };
+    match http.get_channel(id.0).await {
+        Ok(Channel::Guild(channel)) => {
+            db::set_subscription_guild(&data.db_pool, channel_id, &channel.guild_id.to_string())
+                .await?;
+            Ok(None)
+        }
+        Ok(_) => Ok(None),
+        Err(e) => match http_status(&e) {
+            Some(404) => Ok(Some("channel not found")),
+            Some(403) => Ok(Some("missing access")),
+            _ => Err(e).context("failed to get channel"),
+        },
+    }
+}
+
+/// Stops posting to subscribed channels the bot cannot see, and posts again to
+/// the ones it can see again. Returns the number of subscriptions deactivated
+/// and reactivated.
+async fn sweep(http: &Http, data: &Data) -> Result<(u64, u64), Error> {
+    let pool = &data.db_pool;
+    let (mut deactivated, mut reactivated) = (0, 0);
+    for channel_id in db::get_subscribed_channels(pool).await? {
+        // Errors other than 403/404 say nothing about the channel
+        let reason = match check_channel(http, data, &channel_id).await {
+            Ok(reason) => reason,
+            Err(why) => {
+                println!("error checking channel {}: {:?}", channel_id, why);
+                continue;
+            }
+        };
+        let changed = db::set_channel_inactive(pool, &channel_id, reason).await?;
+        match reason {
+            Some(_) => deactivated += changed,
+            None => reactivated += changed,
+        }
+    }
+    Ok((deactivated, reactivated))
+}
+
+/// Spawns a task that sweeps subscribed channels every configured sweep
+/// interval, starting one interval after startup.
+pub fn spawn_sweeper(http: Arc<Http>, data: Data) {
+    tokio::spawn(async move {
+        let period = data.config.tetr.sweep_interval();
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match sweep(&http, &data).await {
+                Ok((0, 0)) => {}
+                Ok((deactivated, reactivated)) => println!(
+                    "Swept channels, deactivated {} and reactivated {} subscriptions",
+                    deactivated, reactivated
+                ),
+                Err(why) => println!("error during channel sweep: {:?}", why),
+            }
+        }
+    });
+}
+
+/// Show monitoring channels the bot cannot post to
+#[poise::command(prefix_command, owners_only, hide_in_help)]
+pub async fn inactive(
+    ctx: Context<'_>,
+    #[description = "Check every channel now instead of waiting for the next sweep"]
+    sweep_now: Option<bool>,
+    #[description = "Delete the subscriptions of inactive channels"] purge: Option<bool>,
+) -> CommandResult {
+    let pool = &ctx.data().db_pool;
+    let mut notes = vec![];
+    if sweep_now.unwrap_or(false) {
+        ctx.defer().await?;
+        let (deactivated, reactivated) = sweep(&ctx.discord().http, ctx.data()).await?;
+        notes.push(format!(
+            "Deactivated {} and reactivated {} subscriptions",
+            deactivated, reactivated
+        ));
+    }
+    if purge.unwrap_or(false) {
+        let deleted = db::delete_inactive_subscriptions(pool).await?;
+        notes.push(format!("Deleted {} inactive subscriptions", deleted));
+    }
+
+    let channels = db::get_inactive_channels(pool).await?;
+    let lines = channels
+        .iter()
+        .map(|c| {
+            format!(
+                "`{}` in `{}` · {} · {} · {} players",
+                c.channel_id,
+                c.guild_id.as_deref().unwrap_or("unknown guild"),
+                c.inactive_reason.as_deref().unwrap_or("unknown"),
+                format_time_ago(&c.inactive_since.to_rfc3339()).unwrap_or_default(),
+                c.subscriptions
+            )
+        })
+        .collect::<Vec<_>>();
+    let subscriptions = channels.iter().map(|c| c.subscriptions).sum::<i64>();
+    ctx.send(|b| {
+        b.embed(|b| {
+            b.title(format!(
+                "{} inactive channels, {} subscriptions",
+                channels.len(),
+                subscriptions
+            ))
+            .description(if lines.is_empty() {
+                "Every monitoring channel can be posted to".into()
+            } else {
+                join_lines(&lines, MAX_LISTED_CHANNELS, EMBED_DESCRIPTION_LIMIT)
+            });
+            if !notes.is_empty() {
+                b.footer(|f| f.text(notes.join("\n")));
+            }
+            b
+        })
+    })
+    .await?;
+    Ok(())
+}
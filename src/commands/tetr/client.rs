@@ -1,14 +1,24 @@
-use crate::{Context, Error};
+use crate::{config::TetrConfig, Error};
 use anyhow::Context as anyhowContext;
+use futures::future::BoxFuture;
+use rand::Rng;
+use serde::de::DeserializeOwned;
 use serde_derive::Deserialize;
-use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+
+mod cache;
+mod endcontext;
+mod limiter;
+
+pub use cache::{CacheStats, TetrCache};
+pub use endcontext::*;
 
 #[allow(dead_code)]
 #[derive(Deserialize)]
 struct TetrResponse<T> {
     success: bool,
     error: Option<String>,
-    cache: Option<Value>,
+    cache: Option<TetrCache>,
     data: Option<T>,
 }
 
@@ -17,6 +27,11 @@ struct UserResponseData {
     user: TetrUser,
 }
 
+#[derive(Deserialize)]
+struct StreamResponseData<T> {
+    records: Vec<T>,
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct TetrUser {
@@ -34,18 +49,73 @@ pub struct TetrUser {
     pub friend_count: Option<i32>,
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct TetraLeagueStanding {
     pub gamesplayed: i32,
+    pub gameswon: i32,
+    /// TR, -1 while the user is unranked
     pub rating: f64,
+    /// Rank letter, `z` while the user is unranked
     pub rank: String,
+    pub glicko: Option<f64>,
+    pub rd: Option<f64>,
+    pub percentile: Option<f64>,
+    pub standing: Option<i32>,
+    pub apm: Option<f64>,
+    pub pps: Option<f64>,
+    pub vs: Option<f64>,
+    #[serde(default)]
+    pub decaying: bool,
 }
 
+/// Tetra League ranks, from lowest to highest
+pub const LEAGUE_RANKS: [&str; 18] = [
+    "z", "d", "d+", "c-", "c", "c+", "b-", "b", "b+", "a-", "a", "a+", "s-", "s", "s+", "ss", "u",
+    "x",
+];
+
+impl TetraLeagueStanding {
+    pub fn is_ranked(&self) -> bool {
+        self.rank != "z"
+    }
+
+    /// Position of this standing's rank in [`LEAGUE_RANKS`]
+    pub fn rank_index(&self) -> Option<usize> {
+        rank_index(&self.rank)
+    }
+
+    /// Attack per piece
+    pub fn app(&self) -> Option<f64> {
+        Some(self.apm? / (self.pps? * 60.0))
+    }
+
+    /// Downstack (garbage cleared) per second, estimated from VS and APM
+    pub fn ds_per_second(&self) -> Option<f64> {
+        Some(self.vs? / 100.0 - self.apm? / 60.0)
+    }
+
+    /// Downstack (garbage cleared) per piece
+    pub fn ds_per_piece(&self) -> Option<f64> {
+        Some(self.ds_per_second()? / self.pps?)
+    }
+
+    pub fn vs_per_apm(&self) -> Option<f64> {
+        Some(self.vs? / self.apm?)
+    }
+}
+
+pub fn rank_index(rank: &str) -> Option<usize> {
+    LEAGUE_RANKS.iter().position(|r| *r == rank)
+}
+
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct TetrUserConnections {
     pub discord: Option<TetrUserDiscordConnection>,
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct TetrUserDiscordConnection {
     pub id: String,
@@ -55,101 +125,546 @@ pub struct TetrUserDiscordConnection {
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct TetrUserRecord {
-    records: TetrUserRankRecord,
-    zen: TetrUserZenRecord,
+    pub records: TetrUserRankRecord,
+    pub zen: TetrUserZenRecord,
+}
+
+impl TetrUserRecord {
+    /// Final time of the 40L personal best in milliseconds
+    pub fn best_40l_ms(&self) -> Option<i32> {
+        self.records
+            ._40l
+            .record
+            .as_ref()
+            .map(|r| r.endcontext.final_time.round() as i32)
+    }
+
+    /// Score of the Blitz personal best
+    pub fn best_blitz_score(&self) -> Option<i32> {
+        self.records
+            .blitz
+            .record
+            .as_ref()
+            .map(|r| r.endcontext.score as i32)
+    }
 }
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct TetrUserRankRecord {
     #[serde(rename = "40l")]
-    _40l: TetrUser40lRecord,
-    blitz: TetrUserBlitzRecord,
+    pub _40l: TetrUser40lRecord,
+    pub blitz: TetrUserBlitzRecord,
 }
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct TetrUser40lRecord {
-    record: Option<TetrRecord>,
-    rank: Option<i32>,
+    pub record: Option<TetrRecord>,
+    pub rank: Option<i32>,
 }
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct TetrUserBlitzRecord {
-    record: Option<TetrRecord>,
-    rank: Option<i32>,
+    pub record: Option<TetrRecord>,
+    pub rank: Option<i32>,
 }
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct TetrUserZenRecord {
-    level: i32,
-    score: i32,
+    pub level: i32,
+    pub score: i32,
 }
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
-pub struct TetrRecord {
-    ts: String,
-    endcontext: Value,
+pub struct TetrRecord<E = SinglePlayerEndContext> {
+    pub _id: String,
+    pub replayid: String,
+    pub ts: String,
+    pub ismulti: Option<bool>,
+    pub endcontext: E,
+}
+
+pub type TetrLeagueRecord = TetrRecord<Vec<MultiplayerEndContext>>;
+
+impl TetrLeagueRecord {
+    /// Endcontext entries of the given user and their opponent
+    pub fn players(
+        &self,
+        user_id: &str,
+    ) -> Option<(&MultiplayerEndContext, &MultiplayerEndContext)> {
+        let me = self.endcontext.iter().find(|p| p.user._id == user_id)?;
+        let opponent = self.endcontext.iter().find(|p| p.user._id != user_id)?;
+        Some((me, opponent))
+    }
 }
 
-static TETR_API_BASE_URL: &str = "https://ch.tetr.io/api/";
 static X_SESSION_ID_HEADER: &str = "X-Session-ID";
-static X_SESSION_ID_VALUE: &str = "aiweufhisdhf";
-
-pub async fn get_user(ctx: &Context<'_>, user: &str) -> Result<TetrUser, Error> {
-    let reqwest = &ctx.data().reqwest;
-    let response = reqwest
-        .get(reqwest::Url::parse(TETR_API_BASE_URL)?.join(&format!("users/{}", user))?)
-        .header(X_SESSION_ID_HEADER, X_SESSION_ID_VALUE)
-        .send()
-        .await
-        .context("error sending request to tetr.io")?
-        .error_for_status()
-        .context("tetr.io API call failed")?
-        .json::<TetrResponse<UserResponseData>>()
-        .await
-        .with_context(|| format!("failed to parse tetr.io data for {}", user))?;
-    if !response.success {
-        Err(anyhow::anyhow!(
-            "tetr.io API unsuccessful for `{}`:\n{}",
-            user,
-            response.error.as_deref().unwrap_or("unknown")
-        ))?;
-    }
-    response
-        .data
-        .map(|r| r.user)
-        .ok_or_else(|| anyhow::anyhow!(format!("user field now found for {}", user)))
+
+#[derive(thiserror::Error, Debug)]
+pub enum TransportError {
+    #[error("tetr.io returned {status}")]
+    Status {
+        status: reqwest::StatusCode,
+        retry_after: Option<Duration>,
+    },
+    #[error("request to tetr.io timed out")]
+    Timeout,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
 }
 
-pub async fn get_user_record(ctx: &Context<'_>, user: &str) -> Result<TetrUserRecord, Error> {
-    let reqwest = &ctx.data().reqwest;
-    let response = reqwest
-        .get(reqwest::Url::parse(TETR_API_BASE_URL)?.join(&format!("users/{}/records", user))?)
-        .header(X_SESSION_ID_HEADER, X_SESSION_ID_VALUE)
-        .send()
-        .await
-        .context("error sending request to tetr.io")?
-        .error_for_status()
-        .context("tetr.io API call failed")?
-        .json::<TetrResponse<TetrUserRecord>>()
+impl TransportError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            TransportError::Status { status, .. } => {
+                *status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            TransportError::Timeout => true,
+            TransportError::Other(_) => false,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TetrError {
+    #[error("gave up on tetr.io request after {attempts} attempts: {last}")]
+    RetriesExhausted {
+        attempts: u32,
+        #[source]
+        last: TransportError,
+    },
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+}
+
+/// Raw access to the tetr.io API, returning response bodies
+pub trait Transport: Send + Sync {
+    /// GETs `path` relative to the API base URL
+    fn get<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<String, TransportError>>;
+}
+
+/// [`Transport`] talking to the real tetr.io API over HTTP
+pub struct HttpTransport {
+    reqwest: reqwest::Client,
+    base_url: reqwest::Url,
+    session_id: String,
+}
+
+impl HttpTransport {
+    pub fn new(config: &TetrConfig) -> Result<Self, Error> {
+        Ok(HttpTransport {
+            reqwest: reqwest::Client::builder()
+                .timeout(config.request_timeout())
+                .build()
+                .context("failed to create HTTP client")?,
+            base_url: reqwest::Url::parse(&config.api_base_url)
+                .context("invalid tetr.io API base URL")?,
+            session_id: config.session_id.clone(),
+        })
+    }
+}
+
+fn map_reqwest_error(e: reqwest::Error, context: &'static str) -> TransportError {
+    if e.is_timeout() {
+        TransportError::Timeout
+    } else {
+        anyhow::Error::new(e).context(context).into()
+    }
+}
+
+impl Transport for HttpTransport {
+    fn get<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<String, TransportError>> {
+        Box::pin(async move {
+            let url = self.base_url.join(path).map_err(anyhow::Error::new)?;
+            let response = self
+                .reqwest
+                .get(url)
+                .header(X_SESSION_ID_HEADER, &self.session_id)
+                .send()
+                .await
+                .map_err(|e| map_reqwest_error(e, "error sending request to tetr.io"))?;
+            let status = response.status();
+            if !status.is_success() {
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .map(Duration::from_secs);
+                return Err(TransportError::Status {
+                    status,
+                    retry_after,
+                });
+            }
+            response
+                .text()
+                .await
+                .map_err(|e| map_reqwest_error(e, "failed to read tetr.io response"))
+        })
+    }
+}
+
+/// Typed tetr.io API client. Responses are cached for as long as tetr.io
+/// reports them as cached, and every request goes through a shared rate
+/// limiter and is retried with backoff on transient failures.
+#[derive(Clone)]
+pub struct TetrClient {
+    transport: Arc<dyn Transport>,
+    cache: Arc<cache::ResponseCache>,
+    limiter: Arc<limiter::RateLimiter>,
+    max_retries: u32,
+    retry_base_delay: Duration,
+}
+
+impl TetrClient {
+    pub fn new(transport: impl Transport + 'static, config: &TetrConfig) -> Self {
+        TetrClient {
+            transport: Arc::new(transport),
+            cache: Default::default(),
+            limiter: Arc::new(limiter::RateLimiter::new(
+                config.requests_per_sec,
+                config.burst,
+            )),
+            max_retries: config.max_retries,
+            retry_base_delay: config.retry_base_delay(),
+        }
+    }
+
+    /// Delay before retry number `retry` (starting at 0), with up to 50% jitter
+    fn backoff(&self, retry: u32) -> Duration {
+        let delay = self.retry_base_delay * 2u32.saturating_pow(retry);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// Longest wait before a retry, asked for by tetr.io or not
+    fn max_delay(&self) -> Duration {
+        self.retry_base_delay * 2u32.saturating_pow(self.max_retries)
+    }
+
+    async fn fetch(&self, path: &str) -> Result<String, TetrError> {
+        let mut retry = 0;
+        loop {
+            self.limiter.acquire().await;
+            let err = match self.transport.get(path).await {
+                Ok(body) => return Ok(body),
+                Err(e) if !e.is_retryable() => return Err(e.into()),
+                Err(e) => e,
+            };
+            let delay = match &err {
+                TransportError::Status {
+                    retry_after: Some(retry_after),
+                    ..
+                } => *retry_after,
+                _ => self.backoff(retry),
+            };
+            // Waiting longer would stall the poll or outlive the interaction
+            if retry >= self.max_retries || delay > self.max_delay() {
+                return Err(TetrError::RetriesExhausted {
+                    attempts: retry + 1,
+                    last: err,
+                });
+            }
+            println!(
+                "tetr.io request to {} failed ({}), retrying in {:?}",
+                path, err, delay
+            );
+            tokio::time::sleep(delay).await;
+            retry += 1;
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    async fn get_data<T: DeserializeOwned>(&self, path: &str, user: &str) -> Result<T, Error> {
+        // The user ends up in the request path and the cache key
+        if !is_valid_user(user) {
+            Err(anyhow::anyhow!(
+                "`{}` is not a valid tetr.io username/id",
+                user
+            ))?;
+        }
+        // Usernames are case insensitive
+        let key = path.to_lowercase();
+        let (body, cached) = match self.cache.get(&key) {
+            Some(body) => (body, true),
+            None => (self.fetch(path).await?, false),
+        };
+        let response = serde_json::from_str::<TetrResponse<T>>(&body)
+            .with_context(|| format!("failed to parse tetr.io data for {}", user))?;
+        if !cached && response.success {
+            if let Some(cache) = &response.cache {
+                self.cache.insert(key, body, cache);
+            }
+        }
+        if !response.success {
+            Err(anyhow::anyhow!(
+                "tetr.io API unsuccessful for `{}`:\n{}",
+                user,
+                response.error.as_deref().unwrap_or("unknown")
+            ))?;
+        }
+        response
+            .data
+            .ok_or_else(|| anyhow::anyhow!("data field not found for {}", user))
+    }
+
+    pub async fn get_user(&self, user: &str) -> Result<TetrUser, Error> {
+        self.get_data::<UserResponseData>(&format!("users/{}", user), user)
+            .await
+            .map(|r| r.user)
+    }
+
+    pub async fn get_user_record(&self, user: &str) -> Result<TetrUserRecord, Error> {
+        self.get_data(&format!("users/{}/records", user), user)
+            .await
+    }
+
+    /// Recent Tetra League matches of a user, newest first. `user_id` must be the
+    /// user's id, usernames are not accepted by the streams endpoint.
+    pub async fn get_user_league_recent(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<TetrLeagueRecord>, Error> {
+        self.get_data::<StreamResponseData<TetrLeagueRecord>>(
+            &format!("streams/league_userrecent_{}", user_id),
+            user_id,
+        )
         .await
-        .with_context(|| format!("failed to parse tetr.io data for {}", user))?;
-    if !response.success {
-        Err(anyhow::anyhow!(
-            "tetr.io API unsuccessful for `{}`:\n{}",
-            user,
-            response.error.as_deref().unwrap_or("unknown")
-        ))?;
-    }
-    response
-        .data
-        .ok_or_else(|| anyhow::anyhow!("data field not found for {}", user))
+        .map(|r| r.records)
+    }
+}
+
+/// Whether a string can be a tetr.io username or user id, so that anything
+/// else is not sent to the API
+pub fn is_valid_user(user: &str) -> bool {
+    let is_id = user.len() == 24 && user.chars().all(|c| c.is_ascii_hexdigit());
+    let is_username = (3..=16).contains(&user.len())
+        && user
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    is_id || is_username
 }
 
 pub fn get_user_avatar_url(user_id: &str) -> String {
     format!("https://tetr.io/user-content/avatars/{}.jpg", user_id)
 }
+
+pub fn get_replay_url(replay_id: &str) -> String {
+    format!("https://tetr.io/#r:{}", replay_id)
+}
+
+#[cfg(test)]
+fn fixture(name: &str) -> String {
+    std::fs::read_to_string(format!(
+        "{}/fixtures/tetr/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    ))
+    .unwrap()
+}
+
+#[cfg(test)]
+fn fixture_data<T: DeserializeOwned>(name: &str) -> T {
+    serde_json::from_str::<TetrResponse<T>>(&fixture(name))
+        .unwrap()
+        .data
+        .unwrap()
+}
+
+#[cfg(test)]
+pub fn fixture_user() -> TetrUser {
+    fixture_data::<UserResponseData>("user.json").user
+}
+
+#[cfg(test)]
+pub fn fixture_user_record() -> TetrUserRecord {
+    fixture_data("user_records.json")
+}
+
+#[cfg(test)]
+pub fn fixture_league_recent() -> Vec<TetrLeagueRecord> {
+    fixture_data::<StreamResponseData<TetrLeagueRecord>>("league_stream.json").records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    fn test_config() -> TetrConfig {
+        TetrConfig {
+            retry_base_delay_ms: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Serves fixture files for known paths
+    struct FixtureTransport(HashMap<&'static str, &'static str>);
+
+    impl Transport for FixtureTransport {
+        fn get<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<String, TransportError>> {
+            Box::pin(async move {
+                let name = self
+                    .0
+                    .get(path)
+                    .ok_or_else(|| anyhow::anyhow!("unexpected path {}", path))?;
+                Ok(fixture(name))
+            })
+        }
+    }
+
+    /// Fails with 503, or 429 when `retry_after` is set, a given number of
+    /// times before serving `user.json`
+    struct FlakyTransport {
+        failures: u32,
+        retry_after: Option<Duration>,
+        calls: AtomicU32,
+    }
+
+    impl Transport for FlakyTransport {
+        fn get<'a>(&'a self, _path: &'a str) -> BoxFuture<'a, Result<String, TransportError>> {
+            Box::pin(async move {
+                if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                    return Err(TransportError::Status {
+                        status: match self.retry_after {
+                            Some(_) => reqwest::StatusCode::TOO_MANY_REQUESTS,
+                            None => reqwest::StatusCode::SERVICE_UNAVAILABLE,
+                        },
+                        retry_after: self.retry_after,
+                    });
+                }
+                Ok(fixture("user.json"))
+            })
+        }
+    }
+
+    fn client() -> TetrClient {
+        TetrClient::new(
+            FixtureTransport(HashMap::from([
+                ("users/osk", "user.json"),
+                ("users/osk/records", "user_records.json"),
+                ("users/nobody", "user_not_found.json"),
+                (
+                    "streams/league_userrecent_5e32fc85ab319c2ab1beb07c",
+                    "league_stream.json",
+                ),
+            ])),
+            &test_config(),
+        )
+    }
+
+    #[tokio::test]
+    async fn gets_user() {
+        let user = client().get_user("osk").await.unwrap();
+        assert_eq!(user._id, "5e32fc85ab319c2ab1beb07c");
+        assert_eq!(user.username, "osk");
+        assert_eq!(user.country.as_deref(), Some("GB"));
+        assert_eq!(user.league.rank, "x");
+        assert_eq!(user.league.rank_index(), Some(LEAGUE_RANKS.len() - 1));
+        assert_eq!(user.league.standing, Some(12));
+        let app = user.league.app().unwrap();
+        assert!((app - 170.92 / (3.02 * 60.0)).abs() < 1e-9);
+        let ds_per_piece = user.league.ds_per_piece().unwrap();
+        assert!((ds_per_piece - (3.5211 - 170.92 / 60.0) / 3.02).abs() < 1e-9);
+        assert_eq!(
+            user.connections.discord.as_ref().map(|d| d.id.as_str()),
+            Some("150388637186244608")
+        );
+    }
+
+    #[tokio::test]
+    async fn gets_user_record() {
+        let record = client().get_user_record("osk").await.unwrap();
+        assert_eq!(record.best_40l_ms(), Some(23457));
+        assert_eq!(record.best_blitz_score(), Some(153012));
+    }
+
+    #[tokio::test]
+    async fn gets_league_recent() {
+        let matches = client()
+            .get_user_league_recent("5e32fc85ab319c2ab1beb07c")
+            .await
+            .unwrap();
+        assert_eq!(matches.len(), 2);
+        let (me, opponent) = matches[0].players("5e32fc85ab319c2ab1beb07c").unwrap();
+        assert_eq!(me.user.username, "osk");
+        assert_eq!(opponent.user.username, "czsmall0428");
+    }
+
+    #[tokio::test]
+    async fn reports_unsuccessful_response() {
+        let err = client().get_user("nobody").await.err().unwrap();
+        assert!(err.to_string().contains("No such user!"));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_users() {
+        for user in ["../../x", "osk/records", "osk?x", "osk#x"] {
+            let err = client().get_user(user).await.err().unwrap();
+            assert!(err.to_string().contains("not a valid tetr.io username/id"));
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let transport = FlakyTransport {
+            failures: 2,
+            retry_after: None,
+            calls: AtomicU32::new(0),
+        };
+        let client = TetrClient::new(transport, &test_config());
+        let user = client.get_user("osk").await.unwrap();
+        assert_eq!(user.username, "osk");
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let transport = FlakyTransport {
+            failures: u32::MAX,
+            retry_after: None,
+            calls: AtomicU32::new(0),
+        };
+        let client = TetrClient::new(transport, &test_config());
+        let err = client.get_user("osk").await.err().unwrap();
+        match err.downcast_ref::<TetrError>() {
+            Some(TetrError::RetriesExhausted { attempts, .. }) => assert_eq!(*attempts, 4),
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_long_retry_after() {
+        let transport = FlakyTransport {
+            failures: 1,
+            retry_after: Some(Duration::from_secs(3600)),
+            calls: AtomicU32::new(0),
+        };
+        let client = TetrClient::new(transport, &test_config());
+        let err = client.get_user("osk").await.err().unwrap();
+        match err.downcast_ref::<TetrError>() {
+            Some(TetrError::RetriesExhausted { attempts, .. }) => assert_eq!(*attempts, 1),
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn validates_users() {
+        assert!(is_valid_user("osk"));
+        assert!(is_valid_user("czsmall0402"));
+        assert!(is_valid_user("a_b-c"));
+        assert!(is_valid_user("5e32fc85ab319c2ab1beb07c"));
+        assert!(!is_valid_user("ab"));
+        assert!(!is_valid_user("x".repeat(17).as_str()));
+        assert!(!is_valid_user("../users/osk"));
+        assert!(!is_valid_user("o sk"));
+    }
+}
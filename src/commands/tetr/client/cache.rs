@@ -0,0 +1,121 @@
+use serde_derive::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Cache metadata sent along with every tetr.io response
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct TetrCache {
+    pub status: String,
+    /// Unix timestamp in milliseconds
+    pub cached_at: i64,
+    /// Unix timestamp in milliseconds
+    pub cached_until: i64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+struct CacheEntry {
+    body: String,
+    cached_until: i64,
+}
+
+/// Response bodies keyed by request path, kept until tetr.io's `cached_until`
+#[derive(Default)]
+pub struct ResponseCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+impl ResponseCache {
+    pub fn get(&self, key: &str) -> Option<String> {
+        let now = now_millis();
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.cached_until > now => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.body.clone())
+            }
+            Some(_) => {
+                entries.remove(key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: String, body: String, cache: &TetrCache) {
+        let now = now_millis();
+        if cache.cached_until <= now {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, e| e.cached_until > now);
+        entries.insert(
+            key,
+            CacheEntry {
+                body,
+                cached_until: cache.cached_until,
+            },
+        );
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_for(millis: i64) -> TetrCache {
+        TetrCache {
+            status: "miss".into(),
+            cached_at: now_millis(),
+            cached_until: now_millis() + millis,
+        }
+    }
+
+    #[test]
+    fn returns_fresh_entries() {
+        let cache = ResponseCache::default();
+        assert_eq!(cache.get("users/osk"), None);
+        cache.insert("users/osk".into(), "body".into(), &cache_for(60_000));
+        assert_eq!(cache.get("users/osk").as_deref(), Some("body"));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    }
+
+    #[test]
+    fn skips_expired_entries() {
+        let cache = ResponseCache::default();
+        cache.insert("users/osk".into(), "body".into(), &cache_for(-1));
+        assert_eq!(cache.get("users/osk"), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
@@ -0,0 +1,230 @@
+use serde_derive::Deserialize;
+
+/// Endcontext of a single-player game (40L, Blitz)
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct SinglePlayerEndContext {
+    pub gametype: String,
+    pub seed: Option<f64>,
+    /// Game length in milliseconds
+    #[serde(rename = "finalTime")]
+    pub final_time: f64,
+    pub score: i64,
+    pub lines: i32,
+    pub level: i32,
+    pub inputs: i32,
+    #[serde(default)]
+    pub holds: i32,
+    pub piecesplaced: i32,
+    pub tspins: i32,
+    pub topcombo: i32,
+    pub topbtb: i32,
+    pub clears: ClearStats,
+    pub garbage: Option<GarbageStats>,
+    pub finesse: Option<FinesseStats>,
+}
+
+#[allow(dead_code)]
+impl SinglePlayerEndContext {
+    /// Pieces per second
+    pub fn pps(&self) -> f64 {
+        if self.final_time <= 0.0 {
+            return 0.0;
+        }
+        self.piecesplaced as f64 / (self.final_time / 1000.0)
+    }
+
+    /// Keys per piece
+    pub fn kpp(&self) -> f64 {
+        if self.piecesplaced == 0 {
+            return 0.0;
+        }
+        self.inputs as f64 / self.piecesplaced as f64
+    }
+
+    pub fn finesse_faults(&self) -> i32 {
+        self.finesse.as_ref().map_or(0, |f| f.faults)
+    }
+
+    pub fn all_clears(&self) -> i32 {
+        self.clears.allclear
+    }
+}
+
+/// Line clear breakdown of a game
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ClearStats {
+    pub singles: i32,
+    pub doubles: i32,
+    pub triples: i32,
+    pub quads: i32,
+    pub realtspins: i32,
+    pub minitspins: i32,
+    pub minitspinsingles: i32,
+    pub tspinsingles: i32,
+    pub minitspindoubles: i32,
+    pub tspindoubles: i32,
+    pub tspintriples: i32,
+    pub tspinquads: i32,
+    pub allclear: i32,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GarbageStats {
+    pub sent: i32,
+    pub received: i32,
+    pub attack: i32,
+    pub cleared: i32,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct FinesseStats {
+    pub combo: i32,
+    pub faults: i32,
+    pub perfectpieces: i32,
+}
+
+/// Endcontext entry of one player in a multiplayer (Tetra League) game
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct MultiplayerEndContext {
+    pub user: MultiplayerUser,
+    pub active: bool,
+    pub success: bool,
+    pub inputs: i32,
+    pub piecesplaced: i32,
+    pub score: i32,
+    /// Number of rounds won
+    pub wins: i32,
+    pub points: MultiplayerPoints,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct MultiplayerUser {
+    pub _id: String,
+    pub username: String,
+}
+
+/// Aggregate and per-round stats of a multiplayer game. `secondary` is APM,
+/// `tertiary` is PPS and `extra.vs` is VS score.
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiplayerPoints {
+    pub primary: i32,
+    pub secondary: f64,
+    pub tertiary: f64,
+    pub extra: MultiplayerExtraPoints,
+    #[serde(default)]
+    pub secondary_avg_tracking: Vec<f64>,
+    #[serde(default)]
+    pub tertiary_avg_tracking: Vec<f64>,
+    #[serde(default)]
+    pub extra_avg_tracking: MultiplayerExtraAvgTracking,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct MultiplayerExtraPoints {
+    pub vs: f64,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MultiplayerExtraAvgTracking {
+    #[serde(rename = "aggregatestats___vsscore", default)]
+    pub vs: Vec<f64>,
+}
+
+#[allow(dead_code)]
+impl MultiplayerPoints {
+    pub fn apm(&self) -> f64 {
+        self.secondary
+    }
+
+    pub fn pps(&self) -> f64 {
+        self.tertiary
+    }
+
+    pub fn vs(&self) -> f64 {
+        self.extra.vs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{fixture, TetrRecord, TetrResponse, TetrUserRecord};
+    use super::*;
+
+    #[test]
+    fn parses_user_records() {
+        let response: TetrResponse<TetrUserRecord> =
+            serde_json::from_str(&fixture("user_records.json")).unwrap();
+        let records = response.data.unwrap();
+
+        let sprint = records.records._40l.record.as_ref().unwrap();
+        let ec = &sprint.endcontext;
+        assert_eq!(ec.gametype, "40l");
+        assert_eq!(ec.final_time, 23456.78);
+        assert_eq!(ec.lines, 40);
+        assert_eq!(ec.piecesplaced, 100);
+        assert_eq!(ec.finesse_faults(), 3);
+        assert_eq!(ec.clears.quads, 10);
+        assert_eq!(ec.all_clears(), 0);
+        assert!((ec.pps() - 4.263).abs() < 0.001);
+        assert!((ec.kpp() - 2.85).abs() < 0.001);
+        assert_eq!(records.records._40l.rank, Some(1234));
+        assert_eq!(records.best_40l_ms(), Some(23457));
+
+        let blitz = records.records.blitz.record.as_ref().unwrap();
+        let ec = &blitz.endcontext;
+        assert_eq!(ec.gametype, "blitz");
+        assert_eq!(ec.score, 153012);
+        assert_eq!(ec.tspins, 21);
+        assert_eq!(ec.clears.tspindoubles, 19);
+        assert_eq!(ec.all_clears(), 2);
+        assert_eq!(records.best_blitz_score(), Some(153012));
+
+        assert_eq!(records.zen.level, 42);
+    }
+
+    #[test]
+    fn parses_missing_records() {
+        let response: TetrResponse<TetrUserRecord> =
+            serde_json::from_str(&fixture("user_records_empty.json")).unwrap();
+        let records = response.data.unwrap();
+        assert!(records.records._40l.record.is_none());
+        assert!(records.records.blitz.record.is_none());
+        assert_eq!(records.best_40l_ms(), None);
+        assert_eq!(records.best_blitz_score(), None);
+    }
+
+    #[test]
+    fn parses_multiplayer_endcontext() {
+        let record: TetrRecord<Vec<MultiplayerEndContext>> =
+            serde_json::from_str(&fixture("league_record.json")).unwrap();
+        assert!(record.ismulti.unwrap());
+        assert_eq!(record.endcontext.len(), 2);
+
+        let winner = &record.endcontext[0];
+        assert_eq!(winner.user.username, "osk");
+        assert!(winner.success);
+        assert_eq!(winner.wins, 7);
+        assert_eq!(winner.points.apm(), 170.12);
+        assert_eq!(winner.points.pps(), 3.01);
+        assert_eq!(winner.points.vs(), 350.27);
+        assert_eq!(winner.points.secondary_avg_tracking.len(), 12);
+        assert_eq!(winner.points.extra_avg_tracking.vs.len(), 12);
+
+        let loser = &record.endcontext[1];
+        assert!(!loser.success);
+        assert_eq!(loser.wins, 5);
+    }
+}
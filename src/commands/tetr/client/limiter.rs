@@ -0,0 +1,46 @@
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket shared by every tetr.io request. Waiters are served in FIFO
+/// order.
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_sec: f64, burst: u32) -> Self {
+        let capacity = f64::from(burst.max(1));
+        RateLimiter {
+            capacity,
+            refill_per_sec: requests_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a request may be sent
+    pub async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+        loop {
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+            bucket.last_refill = now;
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                return;
+            }
+            let wait = (1.0 - bucket.tokens) / self.refill_per_sec;
+            tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+}
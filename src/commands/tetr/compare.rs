@@ -0,0 +1,223 @@
+use super::{
+    autocomplete_monitored_user, client, format_40l_time, format_tetr_user, format_user_title, link,
+};
+use crate::{CommandResult, Context};
+
+use poise::serenity_prelude::CreateEmbed;
+
+/// A compared stat, `None` if a user has no value for it
+struct Row {
+    label: &'static str,
+    a: Option<f64>,
+    b: Option<f64>,
+    higher_is_better: bool,
+    format: fn(f64) -> String,
+}
+
+impl Row {
+    fn new(
+        label: &'static str,
+        a: Option<f64>,
+        b: Option<f64>,
+        higher_is_better: bool,
+        format: fn(f64) -> String,
+    ) -> Self {
+        Row {
+            label,
+            a,
+            b,
+            higher_is_better,
+            format,
+        }
+    }
+
+    /// Whether each user has the better value
+    fn wins(&self) -> (bool, bool) {
+        match (self.a, self.b) {
+            (Some(a), Some(b)) if a == b => (false, false),
+            (Some(a), Some(b)) => (
+                (a > b) == self.higher_is_better,
+                (b > a) == self.higher_is_better,
+            ),
+            (Some(_), None) => (true, false),
+            (None, Some(_)) => (false, true),
+            (None, None) => (false, false),
+        }
+    }
+
+    /// Formatted values of both users, with the better one in bold
+    fn format(&self) -> (String, String) {
+        let (a_wins, b_wins) = self.wins();
+        let cell = |v: Option<f64>, wins: bool| match v {
+            Some(v) if wins => format!("**{}**", (self.format)(v)),
+            Some(v) => (self.format)(v),
+            None => "-".into(),
+        };
+        (cell(self.a, a_wins), cell(self.b, b_wins))
+    }
+}
+
+fn win_rate(won: i32, played: i32) -> Option<f64> {
+    (played > 0).then(|| f64::from(won) / f64::from(played) * 100.0)
+}
+
+fn rows(
+    a: &client::TetrUser,
+    a_record: &client::TetrUserRecord,
+    b: &client::TetrUser,
+    b_record: &client::TetrUserRecord,
+) -> Vec<Row> {
+    let (al, bl) = (&a.league, &b.league);
+    let tr = |l: &client::TetraLeagueStanding| l.is_ranked().then_some(l.rating);
+    let rank = |l: &client::TetraLeagueStanding| {
+        l.is_ranked()
+            .then(|| l.rank_index())
+            .flatten()
+            .map(|i| i as f64)
+    };
+    let fixed2 = |v: f64| format!("{:.2}", v);
+    let fixed3 = |v: f64| format!("{:.3}", v);
+    let percent = |v: f64| format!("{:.1}%", v);
+    vec![
+        Row::new("TR", tr(al), tr(bl), true, fixed2),
+        Row::new("Rank", rank(al), rank(bl), true, |i| {
+            client::LEAGUE_RANKS[i as usize].to_uppercase()
+        }),
+        Row::new("Glicko", al.glicko, bl.glicko, true, |v| {
+            format!("{:.1}", v)
+        }),
+        Row::new(
+            "Global #",
+            al.standing.filter(|s| *s > 0).map(f64::from),
+            bl.standing.filter(|s| *s > 0).map(f64::from),
+            false,
+            |v| format!("#{}", v),
+        ),
+        Row::new("APM", al.apm, bl.apm, true, fixed2),
+        Row::new("PPS", al.pps, bl.pps, true, fixed2),
+        Row::new("VS", al.vs, bl.vs, true, fixed2),
+        Row::new("APP", al.app(), bl.app(), true, fixed3),
+        Row::new(
+            "DS/piece",
+            al.ds_per_piece(),
+            bl.ds_per_piece(),
+            true,
+            fixed3,
+        ),
+        Row::new(
+            "DS/second",
+            al.ds_per_second(),
+            bl.ds_per_second(),
+            true,
+            fixed3,
+        ),
+        Row::new("VS/APM", al.vs_per_apm(), bl.vs_per_apm(), true, fixed3),
+        Row::new(
+            "League win rate",
+            win_rate(al.gameswon, al.gamesplayed),
+            win_rate(bl.gameswon, bl.gamesplayed),
+            true,
+            percent,
+        ),
+        Row::new(
+            "40L",
+            a_record.best_40l_ms().map(f64::from),
+            b_record.best_40l_ms().map(f64::from),
+            false,
+            |v| format_40l_time(v as i32),
+        ),
+        Row::new(
+            "Blitz",
+            a_record.best_blitz_score().map(f64::from),
+            b_record.best_blitz_score().map(f64::from),
+            true,
+            |v| v.to_string(),
+        ),
+        Row::new("Play time", Some(a.gametime), Some(b.gametime), true, |v| {
+            format!("{:.1}h", v / 3600.0)
+        }),
+        Row::new(
+            "Win rate",
+            win_rate(a.gameswon, a.gamesplayed),
+            win_rate(b.gameswon, b.gamesplayed),
+            true,
+            percent,
+        ),
+        Row::new("XP", Some(a.xp), Some(b.xp), true, |v| format!("{:.0}", v)),
+    ]
+}
+
+fn profile_embed(user: &client::TetrUser) -> CreateEmbed {
+    let mut b = CreateEmbed::default();
+    b.title(format_user_title(user));
+    format_tetr_user(user, &mut b);
+    b
+}
+
+/// Compare two tetr.io users side by side
+#[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
+pub async fn compare(
+    ctx: Context<'_>,
+    #[description = "Tetr username/id, @mention or me"]
+    #[autocomplete = "autocomplete_monitored_user"]
+    user_a: String,
+    #[description = "Tetr username/id, @mention or me, compared with yourself by default"]
+    #[autocomplete = "autocomplete_monitored_user"]
+    user_b: Option<String>,
+) -> CommandResult {
+    let (user_a, user_b) = match user_b {
+        Some(user_b) => (
+            link::resolve_user(&ctx, Some(&user_a)).await?,
+            link::resolve_user(&ctx, Some(&user_b)).await?,
+        ),
+        None => (
+            link::resolve_user(&ctx, None).await?,
+            link::resolve_user(&ctx, Some(&user_a)).await?,
+        ),
+    };
+    let tetr = &ctx.data().tetr;
+    let (a, a_record, b, b_record) = tokio::try_join!(
+        tetr.get_user(&user_a),
+        tetr.get_user_record(&user_a),
+        tetr.get_user(&user_b),
+        tetr.get_user_record(&user_b)
+    )?;
+    if a._id == b._id {
+        ctx.say(format!("{} is exactly as good as themselves", a.username))
+            .await?;
+        return Ok(());
+    }
+
+    let rows = rows(&a, &a_record, &b, &b_record);
+    let mut labels = vec![];
+    let mut a_values = vec![];
+    let mut b_values = vec![];
+    let (mut a_wins, mut b_wins) = (0, 0);
+    for row in rows.iter() {
+        let (a_value, b_value) = row.format();
+        let (a_won, b_won) = row.wins();
+        a_wins += a_won as i32;
+        b_wins += b_won as i32;
+        labels.push(row.label);
+        a_values.push(a_value);
+        b_values.push(b_value);
+    }
+
+    let mut comparison = CreateEmbed::default();
+    comparison
+        .title(format!("{} vs {}", a.username, b.username))
+        .description(format!(
+            "{} wins {} stats, {} wins {}",
+            a.username, a_wins, b.username, b_wins
+        ))
+        .field("Stat", labels.join("\n"), true)
+        .field(format_user_title(&a), a_values.join("\n"), true)
+        .field(format_user_title(&b), b_values.join("\n"), true);
+    ctx.send(|m| {
+        m.embeds
+            .extend([profile_embed(&a), profile_embed(&b), comparison]);
+        m
+    })
+    .await?;
+    Ok(())
+}
@@ -1,32 +1,231 @@
 use crate::{DbPool, Error};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 
-#[derive(Debug)]
-pub struct Monitor {
-    pub channel_id: String,
+/// Last known stats of a tetr.io user followed by at least one channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedPlayer {
     pub user_id: String,
     pub username: String,
+    pub country: Option<String>,
     pub last_match_id: Option<String>,
     pub game_time: f64,
     pub games_played: i32,
+    pub games_won: i32,
     pub last_personal_best_blitz: Option<i32>,
     pub last_personal_best_40l: Option<i32>,
+    pub league_rating: Option<f64>,
+    pub league_glicko: Option<f64>,
+    pub league_rd: Option<f64>,
+    pub league_rank: Option<String>,
+    pub league_percentile: Option<f64>,
+    pub league_apm: Option<f64>,
+    pub league_pps: Option<f64>,
+    pub league_vs: Option<f64>,
+    pub league_standing: Option<i32>,
+    /// TR at the time the last TR change was announced
+    pub last_notified_rating: Option<f64>,
+    /// Discord account connected to the tetr.io account
+    pub discord_id: Option<String>,
+}
+
+/// A channel following a tracked player
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub channel_id: String,
+    pub guild_id: Option<String>,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+    /// Events to post, from the subscription or else the channel. `None`
+    /// means every event.
+    pub events: Option<Vec<String>>,
+}
+
+pub async fn get_player(pool: &DbPool, user_id: &str) -> Result<Option<TrackedPlayer>, Error> {
+    sqlx::query_as!(
+        TrackedPlayer,
+        r#"
+SELECT * FROM tracked_player WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to get player")
 }
 
-pub async fn get_monitors_for_channel(
+pub async fn get_players_for_channel(
     pool: &DbPool,
     channel_id: String,
-) -> Result<Vec<Monitor>, Error> {
+) -> Result<Vec<TrackedPlayer>, Error> {
     Ok(sqlx::query_as!(
-        Monitor,
+        TrackedPlayer,
         r#"
-SELECT * FROM monitor WHERE channel_id = $1"#,
+SELECT p.* FROM tracked_player p
+JOIN subscription s ON s.user_id = p.user_id
+WHERE s.channel_id = $1"#,
         channel_id
     )
     .fetch_all(pool)
     .await?)
 }
 
+/// Players followed by any of the channels, each only once
+pub async fn get_players_for_channels(
+    pool: &DbPool,
+    channel_ids: &[String],
+) -> Result<Vec<TrackedPlayer>, Error> {
+    sqlx::query_as!(
+        TrackedPlayer,
+        r#"
+SELECT * FROM tracked_player
+WHERE user_id IN (SELECT user_id FROM subscription WHERE channel_id = ANY($1))"#,
+        channel_ids
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get players")
+}
+
+/// Players followed by any channel of a guild
+pub async fn get_players_for_guild(
+    pool: &DbPool,
+    guild_id: &str,
+) -> Result<Vec<TrackedPlayer>, Error> {
+    sqlx::query_as!(
+        TrackedPlayer,
+        r#"
+SELECT * FROM tracked_player
+WHERE user_id IN (SELECT user_id FROM subscription WHERE guild_id = $1)"#,
+        guild_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get players")
+}
+
+/// Players followed by at least one active channel
+pub async fn get_all_players(pool: &DbPool) -> Result<Vec<TrackedPlayer>, Error> {
+    Ok(sqlx::query_as!(
+        TrackedPlayer,
+        r#"
+SELECT * FROM tracked_player
+WHERE user_id IN (SELECT user_id FROM subscription WHERE inactive_since IS NULL)"#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Subscriptions of channels that can be posted to
+pub async fn get_all_subscriptions(pool: &DbPool) -> Result<Vec<Subscription>, Error> {
+    sqlx::query_as!(
+        Subscription,
+        r#"
+SELECT s.channel_id, s.guild_id, s.user_id, s.created_at, COALESCE(s.events, c.events) AS events
+FROM subscription s
+LEFT JOIN channel_notify c ON c.channel_id = s.channel_id
+WHERE s.inactive_since IS NULL"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get subscriptions")
+}
+
+/// Subscriptions of channels that can be posted to following a player
+pub async fn get_subscriptions_for_player(
+    pool: &DbPool,
+    user_id: &str,
+) -> Result<Vec<Subscription>, Error> {
+    sqlx::query_as!(
+        Subscription,
+        r#"
+SELECT s.channel_id, s.guild_id, s.user_id, s.created_at, COALESCE(s.events, c.events) AS events
+FROM subscription s
+LEFT JOIN channel_notify c ON c.channel_id = s.channel_id
+WHERE s.user_id = $1 AND s.inactive_since IS NULL"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get subscriptions")
+}
+
+/// Fills in the guild of a subscription that was created without it
+pub async fn set_subscription_guild(
+    pool: &DbPool,
+    channel_id: &str,
+    guild_id: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+UPDATE subscription SET guild_id = $2
+WHERE channel_id = $1 AND guild_id IS NULL"#,
+        channel_id,
+        guild_id
+    )
+    .execute(pool)
+    .await
+    .context("failed to set subscription guild")?;
+    Ok(())
+}
+
+pub async fn update_player(pool: &DbPool, player: &TrackedPlayer) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+UPDATE tracked_player
+SET username = $2, country = $3, last_match_id = $4, game_time = $5, games_played = $6,
+    games_won = $7, last_personal_best_blitz = $8, last_personal_best_40l = $9,
+    league_rating = $10, league_glicko = $11, league_rd = $12, league_rank = $13,
+    league_percentile = $14, league_apm = $15, league_pps = $16, league_vs = $17,
+    league_standing = $18, last_notified_rating = $19, discord_id = $20
+WHERE user_id = $1"#,
+        player.user_id,
+        player.username,
+        player.country,
+        player.last_match_id,
+        player.game_time,
+        player.games_played,
+        player.games_won,
+        player.last_personal_best_blitz,
+        player.last_personal_best_40l,
+        player.league_rating,
+        player.league_glicko,
+        player.league_rd,
+        player.league_rank,
+        player.league_percentile,
+        player.league_apm,
+        player.league_pps,
+        player.league_vs,
+        player.league_standing,
+        player.last_notified_rating,
+        player.discord_id,
+    )
+    .execute(pool)
+    .await
+    .context("failed to update player")?;
+    Ok(())
+}
+
+pub async fn insert_rename(
+    pool: &DbPool,
+    user_id: &str,
+    old_username: &str,
+    new_username: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+INSERT INTO username_history (user_id, old_username, new_username)
+VALUES ($1, $2, $3)"#,
+        user_id,
+        old_username,
+        new_username
+    )
+    .execute(pool)
+    .await
+    .context("failed to insert username history")?;
+    Ok(())
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum DbError {
     #[error("{0}")]
@@ -35,63 +234,754 @@ pub enum DbError {
     Internal(#[from] anyhow::Error),
 }
 
-pub async fn insert_monitor(pool: &DbPool, monitor: &Monitor) -> Result<(), DbError> {
+/// Subscribes a channel to a player. The player's stats are only stored if
+/// no other channel follows them yet, so ongoing tracking is not reset.
+pub async fn subscribe(
+    pool: &DbPool,
+    player: &TrackedPlayer,
+    channel_id: &str,
+    guild_id: Option<&str>,
+) -> Result<(), DbError> {
+    let mut tx = pool.begin().await.context("failed to begin transaction")?;
+    sqlx::query!(
+        r#"
+INSERT INTO tracked_player (user_id, username, country, last_match_id, game_time, games_played,
+    games_won, last_personal_best_blitz, last_personal_best_40l, league_rating, league_glicko,
+    league_rd, league_rank, league_percentile, league_apm, league_pps, league_vs,
+    league_standing, last_notified_rating, discord_id)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19,
+    $20)
+ON CONFLICT (user_id) DO NOTHING"#,
+        player.user_id,
+        player.username,
+        player.country,
+        player.last_match_id,
+        player.game_time,
+        player.games_played,
+        player.games_won,
+        player.last_personal_best_blitz,
+        player.last_personal_best_40l,
+        player.league_rating,
+        player.league_glicko,
+        player.league_rd,
+        player.league_rank,
+        player.league_percentile,
+        player.league_apm,
+        player.league_pps,
+        player.league_vs,
+        player.league_standing,
+        player.last_notified_rating,
+        player.discord_id,
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to insert player")?;
+
     let res = sqlx::query!(
         r#"
-INSERT INTO monitor (channel_id, user_id, username, last_match_id, game_time, games_played,
-    last_personal_best_blitz, last_personal_best_40l)
-VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
-        monitor.channel_id,
-        monitor.user_id,
-        monitor.username,
-        monitor.last_match_id,
-        monitor.game_time,
-        monitor.games_played,
-        monitor.last_personal_best_blitz,
-        monitor.last_personal_best_40l,
+INSERT INTO subscription (channel_id, guild_id, user_id) VALUES ($1, $2, $3)"#,
+        channel_id,
+        guild_id,
+        player.user_id
     )
-    .execute(pool)
+    .execute(&mut tx)
     .await;
-
-    match res {
-        Ok(res) => {
-            if res.rows_affected() != 1 {
-                return Err(anyhow::anyhow!(
-                    "failed to insert monitor, rows affected: {}",
-                    res.rows_affected()
-                )
-                .into());
+    if let Err(e) = res {
+        if let Some(code) = e.as_database_error().and_then(|de| de.code()) {
+            if code == "23505" {
+                Err(DbError::Duplicate(
+                    "duplicate error while inserting subscription".into(),
+                ))?
             }
         }
-        Err(e) => {
-            if let Some(code) = e.as_database_error().and_then(|de| de.code()) {
-                if code == "23505" {
-                    Err(DbError::Duplicate(
-                        "duplicate error while inserting monitor".into(),
-                    ))?
-                }
-            }
-            Err(e).context("failed to insert monitor")?;
-        }
+        Err(e).context("failed to insert subscription")?;
+    }
+    tx.commit().await.context("failed to commit subscription")?;
+    Ok(())
+}
+
+/// Unsubscribes a channel from a player, given by username or user id in any
+/// case, and stops tracking the player if no channel follows them anymore.
+/// Returns `None` if the channel does not follow the player.
+pub async fn unsubscribe(
+    pool: &DbPool,
+    channel_id: &str,
+    user: &str,
+) -> Result<Option<TrackedPlayer>, Error> {
+    let mut tx = pool.begin().await?;
+    let player = sqlx::query_as!(
+        TrackedPlayer,
+        r#"
+SELECT p.* FROM tracked_player p
+JOIN subscription s ON s.user_id = p.user_id
+WHERE s.channel_id = $1 AND (lower(p.username) = lower($2) OR p.user_id = lower($2))"#,
+        channel_id,
+        user
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .context("failed to get subscribed player")?;
+    let player = match player {
+        Some(player) => player,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        r#"
+DELETE FROM subscription WHERE channel_id = $1 AND user_id = $2"#,
+        channel_id,
+        player.user_id
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to delete subscription")?;
+    sqlx::query!(
+        r#"
+DELETE FROM tracked_player p
+WHERE user_id = $1 AND NOT EXISTS (SELECT 1 FROM subscription s WHERE s.user_id = p.user_id)"#,
+        player.user_id
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to delete unfollowed player")?;
+    tx.commit().await?;
+    Ok(Some(player))
+}
+
+/// Usernames followed by a channel containing `partial`, prefix matches first
+pub async fn search_monitored_usernames(
+    pool: &DbPool,
+    channel_id: &str,
+    partial: &str,
+    limit: i64,
+) -> Result<Vec<String>, Error> {
+    let rows = sqlx::query!(
+        r#"
+SELECT p.username FROM tracked_player p
+JOIN subscription s ON s.user_id = p.user_id
+WHERE s.channel_id = $1 AND strpos(lower(p.username), lower($2)) > 0
+ORDER BY strpos(lower(p.username), lower($2)), p.username
+LIMIT $3"#,
+        channel_id,
+        partial,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to search monitored usernames")?;
+    Ok(rows.into_iter().map(|r| r.username).collect())
+}
+
+/// Events a channel posts for a player, `None` if it uses the channel's
+pub struct PlayerEvents {
+    pub user_id: String,
+    pub username: String,
+    pub events: Option<Vec<String>>,
+}
+
+/// Events a channel posts, `None` if it posts every event
+pub async fn get_channel_events(
+    pool: &DbPool,
+    channel_id: &str,
+) -> Result<Option<Vec<String>>, Error> {
+    let row = sqlx::query!(
+        r#"
+SELECT events FROM channel_notify WHERE channel_id = $1"#,
+        channel_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to get channel events")?;
+    Ok(row.map(|r| r.events))
+}
+
+/// Sets the events a channel posts, every event if `None`
+pub async fn set_channel_events(
+    pool: &DbPool,
+    channel_id: &str,
+    events: Option<&[String]>,
+) -> Result<(), Error> {
+    match events {
+        Some(events) => sqlx::query!(
+            r#"
+INSERT INTO channel_notify (channel_id, events) VALUES ($1, $2)
+ON CONFLICT (channel_id) DO UPDATE SET events = EXCLUDED.events"#,
+            channel_id,
+            events
+        )
+        .execute(pool)
+        .await
+        .context("failed to set channel events")?,
+        None => sqlx::query!(
+            r#"
+DELETE FROM channel_notify WHERE channel_id = $1"#,
+            channel_id
+        )
+        .execute(pool)
+        .await
+        .context("failed to delete channel events")?,
     };
     Ok(())
 }
 
-pub async fn delete_monitor(
+/// Events of a player followed by a channel, given by username or user id in
+/// any case. Returns `None` if the channel does not follow the player.
+pub async fn get_player_events(
     pool: &DbPool,
     channel_id: &str,
-    username: &str,
-) -> Result<Monitor, DbError> {
-    Ok(sqlx::query_as!(
-        Monitor,
+    user: &str,
+) -> Result<Option<PlayerEvents>, Error> {
+    sqlx::query_as!(
+        PlayerEvents,
+        r#"
+SELECT p.user_id, p.username, s.events FROM tracked_player p
+JOIN subscription s ON s.user_id = p.user_id
+WHERE s.channel_id = $1 AND (lower(p.username) = lower($2) OR p.user_id = lower($2))"#,
+        channel_id,
+        user
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to get player events")
+}
+
+/// Players of a channel with their own events
+pub async fn get_player_events_for_channel(
+    pool: &DbPool,
+    channel_id: &str,
+) -> Result<Vec<PlayerEvents>, Error> {
+    sqlx::query_as!(
+        PlayerEvents,
+        r#"
+SELECT p.user_id, p.username, s.events FROM tracked_player p
+JOIN subscription s ON s.user_id = p.user_id
+WHERE s.channel_id = $1 AND s.events IS NOT NULL
+ORDER BY p.username"#,
+        channel_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get player events")
+}
+
+/// Sets the events a channel posts for a player, the channel's if `None`
+pub async fn set_player_events(
+    pool: &DbPool,
+    channel_id: &str,
+    user_id: &str,
+    events: Option<&[String]>,
+) -> Result<(), Error> {
+    sqlx::query!(
         r#"
-DELETE FROM monitor
-WHERE channel_id = $1 AND username = $2
-RETURNING *"#,
+UPDATE subscription SET events = $3 WHERE channel_id = $1 AND user_id = $2"#,
         channel_id,
-        username
+        user_id,
+        events
+    )
+    .execute(pool)
+    .await
+    .context("failed to set player events")?;
+    Ok(())
+}
+
+/// Channels the bot stopped posting to, with how many players they follow
+pub struct InactiveChannel {
+    pub channel_id: String,
+    pub guild_id: Option<String>,
+    pub inactive_since: DateTime<Utc>,
+    pub inactive_reason: Option<String>,
+    pub subscriptions: i64,
+}
+
+/// Every channel following at least one player, active or not
+pub async fn get_subscribed_channels(pool: &DbPool) -> Result<Vec<String>, Error> {
+    let rows = sqlx::query!(
+        r#"
+SELECT DISTINCT channel_id FROM subscription"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get subscribed channels")?;
+    Ok(rows.into_iter().map(|r| r.channel_id).collect())
+}
+
+/// Stops posting to a channel, or posts to it again if `reason` is `None`.
+/// Returns the number of subscriptions that changed.
+pub async fn set_channel_inactive(
+    pool: &DbPool,
+    channel_id: &str,
+    reason: Option<&str>,
+) -> Result<u64, Error> {
+    let res = match reason {
+        Some(reason) => sqlx::query!(
+            r#"
+UPDATE subscription SET inactive_since = now(), inactive_reason = $2
+WHERE channel_id = $1 AND inactive_since IS NULL"#,
+            channel_id,
+            reason
+        )
+        .execute(pool)
+        .await
+        .context("failed to deactivate subscriptions")?,
+        None => sqlx::query!(
+            r#"
+UPDATE subscription SET inactive_since = NULL, inactive_reason = NULL
+WHERE channel_id = $1 AND inactive_since IS NOT NULL"#,
+            channel_id
+        )
+        .execute(pool)
+        .await
+        .context("failed to reactivate subscriptions")?,
+    };
+    Ok(res.rows_affected())
+}
+
+pub async fn get_inactive_channels(pool: &DbPool) -> Result<Vec<InactiveChannel>, Error> {
+    sqlx::query_as!(
+        InactiveChannel,
+        r#"
+SELECT channel_id, max(guild_id) AS guild_id, min(inactive_since) AS "inactive_since!",
+    max(inactive_reason) AS inactive_reason, count(*) AS "subscriptions!"
+FROM subscription
+WHERE inactive_since IS NOT NULL
+GROUP BY channel_id
+ORDER BY min(inactive_since)"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get inactive channels")
+}
+
+/// Deletes the subscriptions and event filters of the channels, and players
+/// no channel follows anymore. Returns the number of deleted subscriptions.
+async fn delete_subscriptions_where(
+    pool: &DbPool,
+    channel_ids: Option<&[String]>,
+    guild_id: Option<&str>,
+    inactive_only: bool,
+) -> Result<u64, Error> {
+    let mut tx = pool.begin().await?;
+    let deleted = sqlx::query!(
+        r#"
+DELETE FROM subscription
+WHERE (channel_id = ANY($1) OR guild_id = $2 OR ($3 AND inactive_since IS NOT NULL))
+RETURNING channel_id"#,
+        channel_ids,
+        guild_id,
+        inactive_only
+    )
+    .fetch_all(&mut tx)
+    .await
+    .context("failed to delete subscriptions")?;
+    let channel_ids = deleted
+        .into_iter()
+        .map(|r| r.channel_id)
+        .collect::<Vec<_>>();
+    sqlx::query!(
+        r#"
+DELETE FROM channel_notify c
+WHERE channel_id = ANY($1)
+    AND NOT EXISTS (SELECT 1 FROM subscription s WHERE s.channel_id = c.channel_id)"#,
+        &channel_ids
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to delete channel events")?;
+    sqlx::query!(
+        r#"
+DELETE FROM tracked_player p
+WHERE NOT EXISTS (SELECT 1 FROM subscription s WHERE s.user_id = p.user_id)"#
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to delete unfollowed players")?;
+    tx.commit().await?;
+    Ok(channel_ids.len() as u64)
+}
+
+pub async fn delete_channel_subscriptions(pool: &DbPool, channel_id: &str) -> Result<u64, Error> {
+    delete_subscriptions_where(pool, Some(&[channel_id.to_string()]), None, false).await
+}
+
+/// Deletes the subscriptions and rank roles of a guild, and the subscriptions
+/// of its channels that do not know their guild
+pub async fn delete_guild_subscriptions(
+    pool: &DbPool,
+    guild_id: &str,
+    channel_ids: &[String],
+) -> Result<u64, Error> {
+    sqlx::query!(
+        r#"
+DELETE FROM rank_role WHERE guild_id = $1"#,
+        guild_id
+    )
+    .execute(pool)
+    .await
+    .context("failed to delete rank roles")?;
+    delete_rank_role_syncs(pool, guild_id).await?;
+    delete_subscriptions_where(pool, Some(channel_ids), Some(guild_id), false).await
+}
+
+pub async fn delete_inactive_subscriptions(pool: &DbPool) -> Result<u64, Error> {
+    delete_subscriptions_where(pool, None, None, true).await
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct UserSnapshot {
+    pub id: i64,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+    pub league_rating: Option<f64>,
+    pub league_rank: Option<String>,
+    pub personal_best_40l: Option<i32>,
+    pub personal_best_blitz: Option<i32>,
+    pub game_time: f64,
+    pub games_played: i32,
+    pub games_won: i32,
+    pub xp: f64,
+}
+
+/// Stores a snapshot of a user's stats, unless one was already stored for the
+/// user within `min_interval_secs`. Returns whether a snapshot was stored.
+pub async fn insert_snapshot(
+    pool: &DbPool,
+    snapshot: &UserSnapshot,
+    min_interval_secs: i64,
+) -> Result<bool, Error> {
+    let res = sqlx::query!(
+        r#"
+INSERT INTO user_snapshot (user_id, league_rating, league_rank, personal_best_40l,
+    personal_best_blitz, game_time, games_played, games_won, xp)
+SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9
+WHERE NOT EXISTS (
+    SELECT 1 FROM user_snapshot
+    WHERE user_id = $1 AND created_at > now() - make_interval(secs => $10)
+)"#,
+        snapshot.user_id,
+        snapshot.league_rating,
+        snapshot.league_rank,
+        snapshot.personal_best_40l,
+        snapshot.personal_best_blitz,
+        snapshot.game_time,
+        snapshot.games_played,
+        snapshot.games_won,
+        snapshot.xp,
+        min_interval_secs as f64,
+    )
+    .execute(pool)
+    .await
+    .context("failed to insert snapshot")?;
+    Ok(res.rows_affected() == 1)
+}
+
+/// Snapshots of a user taken at or after `since`, or every snapshot if `None`,
+/// oldest first
+pub async fn get_snapshots(
+    pool: &DbPool,
+    user_id: &str,
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<UserSnapshot>, Error> {
+    sqlx::query_as!(
+        UserSnapshot,
+        r#"
+SELECT * FROM user_snapshot
+WHERE user_id = $1 AND ($2::timestamptz IS NULL OR created_at >= $2)
+ORDER BY created_at"#,
+        user_id,
+        since
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
+    .await
+    .context("failed to get snapshots")
+}
+
+/// Keeps only the last snapshot of each day for snapshots older than
+/// `full_resolution_days`, and deletes snapshots older than `retention_days`
+/// (kept forever if 0). Returns the number of deleted rows.
+pub async fn prune_snapshots(
+    pool: &DbPool,
+    full_resolution_days: i32,
+    retention_days: i32,
+) -> Result<u64, Error> {
+    let downsampled = sqlx::query!(
+        r#"
+DELETE FROM user_snapshot
+WHERE created_at < now() - make_interval(days => $1)
+AND id NOT IN (
+    SELECT DISTINCT ON (user_id, date_trunc('day', created_at)) id
+    FROM user_snapshot
+    WHERE created_at < now() - make_interval(days => $1)
+    ORDER BY user_id, date_trunc('day', created_at), created_at DESC
+)"#,
+        full_resolution_days
+    )
+    .execute(pool)
     .await
-    .context("failed to delete monitor")?)
+    .context("failed to downsample snapshots")?
+    .rows_affected();
+
+    let expired = if retention_days > 0 {
+        sqlx::query!(
+            r#"
+DELETE FROM user_snapshot
+WHERE created_at < now() - make_interval(days => $1)"#,
+            retention_days
+        )
+        .execute(pool)
+        .await
+        .context("failed to delete expired snapshots")?
+        .rows_affected()
+    } else {
+        0
+    };
+    Ok(downsampled + expired)
+}
+
+/// A row of the last leaderboard posted for a scope and metric
+#[derive(Debug, Clone)]
+pub struct LeaderboardEntry {
+    pub user_id: String,
+    pub position: i32,
+    pub value: f64,
+    pub posted_at: DateTime<Utc>,
+}
+
+pub async fn get_leaderboard(
+    pool: &DbPool,
+    scope: &str,
+    metric: &str,
+) -> Result<Vec<LeaderboardEntry>, Error> {
+    sqlx::query_as!(
+        LeaderboardEntry,
+        r#"
+SELECT user_id, position, value, posted_at FROM leaderboard_entry
+WHERE scope = $1 AND metric = $2
+ORDER BY position"#,
+        scope,
+        metric
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get leaderboard")
+}
+
+/// Replaces the last posted leaderboard of a scope and metric
+pub async fn set_leaderboard(
+    pool: &DbPool,
+    scope: &str,
+    metric: &str,
+    entries: &[LeaderboardEntry],
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+DELETE FROM leaderboard_entry WHERE scope = $1 AND metric = $2"#,
+        scope,
+        metric
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to delete leaderboard")?;
+    for entry in entries {
+        sqlx::query!(
+            r#"
+INSERT INTO leaderboard_entry (scope, metric, user_id, position, value, posted_at)
+VALUES ($1, $2, $3, $4, $5, $6)"#,
+            scope,
+            metric,
+            entry.user_id,
+            entry.position,
+            entry.value,
+            entry.posted_at
+        )
+        .execute(&mut tx)
+        .await
+        .context("failed to insert leaderboard entry")?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Tetr.io user id linked to a Discord user
+pub async fn get_linked_user(pool: &DbPool, discord_id: &str) -> Result<Option<String>, Error> {
+    let row = sqlx::query!(
+        r#"
+SELECT user_id FROM discord_link WHERE discord_id = $1"#,
+        discord_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to get linked user")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+/// Links a Discord user to a tetr.io user, replacing any previous link of
+/// either of them
+pub async fn set_linked_user(pool: &DbPool, discord_id: &str, user_id: &str) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+DELETE FROM discord_link WHERE discord_id = $1 OR user_id = $2"#,
+        discord_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to delete previous links")?;
+    sqlx::query!(
+        r#"
+INSERT INTO discord_link (discord_id, user_id) VALUES ($1, $2)"#,
+        discord_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await
+    .context("failed to insert link")?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Returns the tetr.io user id that was linked, if any
+pub async fn delete_linked_user(pool: &DbPool, discord_id: &str) -> Result<Option<String>, Error> {
+    let row = sqlx::query!(
+        r#"
+DELETE FROM discord_link WHERE discord_id = $1
+RETURNING user_id"#,
+        discord_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to delete link")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+#[derive(Debug, Clone)]
+pub struct RankRole {
+    pub guild_id: String,
+    pub rank: String,
+    pub role_id: String,
+}
+
+pub async fn get_rank_roles(pool: &DbPool, guild_id: &str) -> Result<Vec<RankRole>, Error> {
+    sqlx::query_as!(
+        RankRole,
+        r#"
+SELECT * FROM rank_role WHERE guild_id = $1"#,
+        guild_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get rank roles")
+}
+
+/// Guilds with at least one rank role
+pub async fn get_rank_role_guilds(pool: &DbPool) -> Result<Vec<String>, Error> {
+    let rows = sqlx::query!(
+        r#"
+SELECT DISTINCT guild_id FROM rank_role"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get rank role guilds")?;
+    Ok(rows.into_iter().map(|r| r.guild_id).collect())
+}
+
+pub async fn set_rank_role(pool: &DbPool, rank_role: &RankRole) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+INSERT INTO rank_role (guild_id, rank, role_id) VALUES ($1, $2, $3)
+ON CONFLICT (guild_id, rank) DO UPDATE SET role_id = EXCLUDED.role_id"#,
+        rank_role.guild_id,
+        rank_role.rank,
+        rank_role.role_id
+    )
+    .execute(pool)
+    .await
+    .context("failed to set rank role")?;
+    Ok(())
+}
+
+/// Returns whether the rank had a role
+pub async fn delete_rank_role(pool: &DbPool, guild_id: &str, rank: &str) -> Result<bool, Error> {
+    let res = sqlx::query!(
+        r#"
+DELETE FROM rank_role WHERE guild_id = $1 AND rank = $2"#,
+        guild_id,
+        rank
+    )
+    .execute(pool)
+    .await
+    .context("failed to delete rank role")?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Rank role last given to a player's Discord account in a guild
+#[derive(Debug, Clone, PartialEq)]
+pub struct RankRoleSync {
+    pub guild_id: String,
+    pub user_id: String,
+    pub discord_id: String,
+    pub role_id: Option<String>,
+}
+
+pub async fn get_rank_role_syncs(
+    pool: &DbPool,
+    guild_id: &str,
+) -> Result<Vec<RankRoleSync>, Error> {
+    sqlx::query_as!(
+        RankRoleSync,
+        r#"
+SELECT * FROM rank_role_sync WHERE guild_id = $1"#,
+        guild_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to get rank role syncs")
+}
+
+pub async fn set_rank_role_sync(pool: &DbPool, sync: &RankRoleSync) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+INSERT INTO rank_role_sync (guild_id, user_id, discord_id, role_id) VALUES ($1, $2, $3, $4)
+ON CONFLICT (guild_id, user_id) DO UPDATE
+SET discord_id = EXCLUDED.discord_id, role_id = EXCLUDED.role_id"#,
+        sync.guild_id,
+        sync.user_id,
+        sync.discord_id,
+        sync.role_id
+    )
+    .execute(pool)
+    .await
+    .context("failed to set rank role sync")?;
+    Ok(())
+}
+
+pub async fn delete_rank_role_sync(
+    pool: &DbPool,
+    guild_id: &str,
+    user_id: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+DELETE FROM rank_role_sync WHERE guild_id = $1 AND user_id = $2"#,
+        guild_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("failed to delete rank role sync")?;
+    Ok(())
+}
+
+/// Makes the next sync check every member of a guild again
+pub async fn delete_rank_role_syncs(pool: &DbPool, guild_id: &str) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+DELETE FROM rank_role_sync WHERE guild_id = $1"#,
+        guild_id
+    )
+    .execute(pool)
+    .await
+    .context("failed to delete rank role syncs")?;
+    Ok(())
 }
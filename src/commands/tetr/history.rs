@@ -0,0 +1,134 @@
+use super::{autocomplete_monitored_user, chart, db, db::UserSnapshot, format_40l_time, link};
+use crate::{CommandResult, Context};
+
+use chrono::{DateTime, Duration, Utc};
+use poise::serenity_prelude::AttachmentType;
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum HistoryMetric {
+    #[name = "TR"]
+    Tr,
+    #[name = "40L"]
+    Sprint,
+    #[name = "Blitz"]
+    Blitz,
+    #[name = "Playtime"]
+    Playtime,
+}
+
+impl HistoryMetric {
+    fn unit(self) -> &'static str {
+        match self {
+            HistoryMetric::Tr => "TR",
+            HistoryMetric::Sprint => "Seconds",
+            HistoryMetric::Blitz => "Score",
+            HistoryMetric::Playtime => "Hours",
+        }
+    }
+
+    fn value(self, s: &UserSnapshot) -> Option<f64> {
+        match self {
+            HistoryMetric::Tr => s.league_rating,
+            HistoryMetric::Sprint => s.personal_best_40l.map(|t| t as f64 / 1000.0),
+            HistoryMetric::Blitz => s.personal_best_blitz.map(f64::from),
+            HistoryMetric::Playtime => Some(s.game_time / 3600.0),
+        }
+    }
+
+    fn format(self, s: &UserSnapshot) -> Option<String> {
+        match self {
+            HistoryMetric::Sprint => s.personal_best_40l.map(format_40l_time),
+            HistoryMetric::Playtime => Some(format!("{:.1}h", s.game_time / 3600.0)),
+            _ => self.value(s).map(|v| format!("{:.2}", v)),
+        }
+    }
+}
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum HistoryRange {
+    #[name = "Week"]
+    Week,
+    #[name = "Month"]
+    Month,
+    #[name = "Year"]
+    Year,
+    #[name = "All"]
+    All,
+}
+
+impl HistoryRange {
+    /// Start of the range, `None` for every snapshot
+    fn since(self) -> Option<DateTime<Utc>> {
+        let days = match self {
+            HistoryRange::Week => 7,
+            HistoryRange::Month => 30,
+            HistoryRange::Year => 365,
+            HistoryRange::All => return None,
+        };
+        Some(Utc::now() - Duration::days(days))
+    }
+}
+
+/// Chart the progress of a monitored tetr.io user
+#[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "Tetr username/id or @mention, yourself by default"]
+    #[autocomplete = "autocomplete_monitored_user"]
+    user: Option<String>,
+    #[description = "Stat to chart, TR by default"] metric: Option<HistoryMetric>,
+    #[description = "Time range, a month by default"] range: Option<HistoryRange>,
+) -> CommandResult {
+    let metric = metric.unwrap_or(HistoryMetric::Tr);
+    let range = range.unwrap_or(HistoryRange::Month);
+    let user = link::resolve_user(&ctx, user.as_deref()).await?;
+    let user_data = ctx.data().tetr.get_user(&user).await?;
+    let snapshots = db::get_snapshots(&ctx.data().db_pool, &user_data._id, range.since()).await?;
+    let points = snapshots
+        .iter()
+        .filter_map(|s| metric.value(s).map(|v| (s.created_at, v)))
+        .collect::<Vec<_>>();
+    if points.len() < 2 {
+        ctx.say(format!(
+            "Not enough {} history for {} yet, only monitored users are recorded",
+            metric.name(),
+            user_data.username
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let title = format!(
+        "{} {} ({})",
+        user_data.username,
+        metric.name(),
+        range.name()
+    );
+    let png = tokio::task::spawn_blocking(move || {
+        chart::render_line_chart(&title, metric.unit(), &points)
+    })
+    .await??;
+
+    let first = snapshots.iter().find_map(|s| metric.format(s));
+    let latest = snapshots.iter().rev().find_map(|s| metric.format(s));
+    ctx.send(|b| {
+        b.attachment(AttachmentType::Bytes {
+            data: png.into(),
+            filename: "history.png".into(),
+        })
+        .embed(|b| {
+            b.title(format!(
+                "{} history of {}",
+                metric.name(),
+                user_data.username
+            ))
+            .image("attachment://history.png");
+            if let (Some(first), Some(latest)) = (first, latest) {
+                b.field("From", first, true).field("To", latest, true);
+            }
+            b
+        })
+    })
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use super::{
+    db, db::LeaderboardEntry, db::TrackedPlayer, format_40l_time, format_time_ago, join_lines,
+    EMBED_DESCRIPTION_LIMIT,
+};
+use crate::{CommandResult, Context};
+
+use country_emoji::code_to_flag;
+use std::collections::HashMap;
+
+/// Rows shown in the embed, the rest are still stored for the next diff
+const MAX_ROWS: usize = 25;
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum LeaderboardMetric {
+    #[name = "TR"]
+    Tr,
+    #[name = "40L"]
+    Sprint,
+    #[name = "Blitz"]
+    Blitz,
+    #[name = "Playtime"]
+    Playtime,
+    #[name = "Win rate"]
+    WinRate,
+}
+
+impl LeaderboardMetric {
+    /// Stable name stored with posted leaderboards
+    fn key(self) -> &'static str {
+        match self {
+            LeaderboardMetric::Tr => "tr",
+            LeaderboardMetric::Sprint => "40l",
+            LeaderboardMetric::Blitz => "blitz",
+            LeaderboardMetric::Playtime => "playtime",
+            LeaderboardMetric::WinRate => "winrate",
+        }
+    }
+
+    fn value(self, m: &TrackedPlayer) -> Option<f64> {
+        match self {
+            LeaderboardMetric::Tr => m.league_rating,
+            LeaderboardMetric::Sprint => m.last_personal_best_40l.map(f64::from),
+            LeaderboardMetric::Blitz => m.last_personal_best_blitz.map(f64::from),
+            LeaderboardMetric::Playtime => Some(m.game_time),
+            LeaderboardMetric::WinRate => (m.games_played > 0)
+                .then(|| f64::from(m.games_won) / f64::from(m.games_played) * 100.0),
+        }
+    }
+
+    fn lower_is_better(self) -> bool {
+        matches!(self, LeaderboardMetric::Sprint)
+    }
+
+    fn format(self, v: f64) -> String {
+        match self {
+            LeaderboardMetric::Tr => format!("{:.2} TR", v),
+            LeaderboardMetric::Sprint => format_40l_time(v.round() as i32),
+            LeaderboardMetric::Blitz => format!("{}", v.round()),
+            LeaderboardMetric::Playtime => format!("{:.1}h", v / 3600.0),
+            LeaderboardMetric::WinRate => format!("{:.1}%", v),
+        }
+    }
+
+    /// Signed change of a value, `None` if it is too small to show
+    fn format_delta(self, d: f64) -> Option<String> {
+        let (min, formatted) = match self {
+            LeaderboardMetric::Tr => (0.005, format!("{:.2}", d.abs())),
+            LeaderboardMetric::Sprint => (0.5, format_40l_time(d.abs().round() as i32)),
+            LeaderboardMetric::Blitz => (0.5, format!("{}", d.abs().round())),
+            LeaderboardMetric::Playtime => (180.0, format!("{:.1}h", d.abs() / 3600.0)),
+            LeaderboardMetric::WinRate => (0.05, format!("{:.1}%", d.abs())),
+        };
+        (d.abs() >= min).then(|| format!("{}{}", if d < 0.0 { "-" } else { "+" }, formatted))
+    }
+}
+
+#[derive(poise::ChoiceParameter, Clone, Copy, PartialEq)]
+pub enum LeaderboardScope {
+    #[name = "Channel"]
+    Channel,
+    #[name = "Server"]
+    Server,
+}
+
+fn rank_players(
+    metric: LeaderboardMetric,
+    players: Vec<TrackedPlayer>,
+) -> Vec<(TrackedPlayer, f64)> {
+    let mut rows = players
+        .into_iter()
+        .filter_map(|m| metric.value(&m).map(|v| (m, v)))
+        .collect::<Vec<_>>();
+    rows.sort_by(|(a, x), (b, y)| {
+        let ord = if metric.lower_is_better() {
+            x.total_cmp(y)
+        } else {
+            y.total_cmp(x)
+        };
+        ord.then_with(|| a.username.cmp(&b.username))
+    });
+    rows
+}
+
+fn format_row(
+    metric: LeaderboardMetric,
+    position: usize,
+    m: &TrackedPlayer,
+    value: f64,
+    previous: Option<&LeaderboardEntry>,
+) -> String {
+    let flag = code_to_flag(m.country.as_deref().unwrap_or_default()).unwrap_or_default();
+    let mut line = format!(
+        "`#{:<2}` {} **{}** {}",
+        position,
+        flag,
+        m.username,
+        metric.format(value)
+    );
+    match previous {
+        Some(prev) => {
+            let moved = prev.position - position as i32;
+            if moved > 0 {
+                line.push_str(&format!(" ▲{}", moved));
+            } else if moved < 0 {
+                line.push_str(&format!(" ▼{}", -moved));
+            }
+            if let Some(delta) = metric.format_delta(value - prev.value) {
+                line.push_str(&format!(" ({})", delta));
+            }
+        }
+        None => line.push_str(" 🆕"),
+    }
+    line
+}
+
+/// Rank the tetr.io users monitored in this channel or server
+#[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
+pub async fn leaderboard(
+    ctx: Context<'_>,
+    #[description = "Stat to rank by, TR by default"] metric: Option<LeaderboardMetric>,
+    #[description = "Channel or whole server, channel by default"] scope: Option<LeaderboardScope>,
+) -> CommandResult {
+    let metric = metric.unwrap_or(LeaderboardMetric::Tr);
+    let scope = scope.unwrap_or(LeaderboardScope::Channel);
+    let pool = &ctx.data().db_pool;
+
+    let (scope_key, players) = match (scope, ctx.guild_id()) {
+        (LeaderboardScope::Server, Some(guild_id)) => {
+            let channel_ids = guild_id
+                .channels(ctx.discord())
+                .await?
+                .into_keys()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>();
+            (
+                format!("guild:{}", guild_id),
+                db::get_players_for_channels(pool, &channel_ids).await?,
+            )
+        }
+        _ => (
+            format!("channel:{}", ctx.channel_id()),
+            db::get_players_for_channel(pool, ctx.channel_id().to_string()).await?,
+        ),
+    };
+
+    let rows = rank_players(metric, players);
+    if rows.is_empty() {
+        ctx.say(format!("No monitored users with a {}", metric.name()))
+            .await?;
+        return Ok(());
+    }
+
+    let previous = db::get_leaderboard(pool, &scope_key, metric.key()).await?;
+    let previous_posted_at = previous.first().map(|e| e.posted_at);
+    let previous = previous
+        .into_iter()
+        .map(|e| (e.user_id.clone(), e))
+        .collect::<HashMap<_, _>>();
+
+    let lines = rows
+        .iter()
+        .enumerate()
+        .map(|(i, (m, v))| format_row(metric, i + 1, m, *v, previous.get(&m.user_id)))
+        .collect::<Vec<_>>();
+
+    let now = chrono::Utc::now();
+    let entries = rows
+        .iter()
+        .enumerate()
+        .map(|(i, (m, v))| LeaderboardEntry {
+            user_id: m.user_id.clone(),
+            position: i as i32 + 1,
+            value: *v,
+            posted_at: now,
+        })
+        .collect::<Vec<_>>();
+    db::set_leaderboard(pool, &scope_key, metric.key(), &entries).await?;
+
+    let footer = match previous_posted_at.and_then(|t| format_time_ago(&t.to_rfc3339())) {
+        Some(ago) => format!("Changes since the leaderboard posted {}", ago),
+        None => "First leaderboard posted here".into(),
+    };
+    ctx.send(|b| {
+        b.embed(|b| {
+            b.title(format!(
+                "{} leaderboard of this {}",
+                metric.name(),
+                if scope == LeaderboardScope::Server && ctx.guild_id().is_some() {
+                    "server"
+                } else {
+                    "channel"
+                }
+            ))
+            .description(join_lines(&lines, MAX_ROWS, EMBED_DESCRIPTION_LIMIT))
+            .footer(|b| b.text(footer))
+        })
+    })
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use super::{client, db, format_tetr_user, format_user_title};
+use crate::{CommandResult, Context, Error};
+
+use poise::serenity_prelude::utils::parse_username;
+
+/// Resolves a command's user argument to a tetr.io username or id. `me` (or
+/// no argument) means the author's linked account, and a mention means the
+/// mentioned user's linked account.
+pub async fn resolve_user(ctx: &Context<'_>, user: Option<&str>) -> Result<String, Error> {
+    let pool = &ctx.data().db_pool;
+    match user.map(str::trim) {
+        None => resolve_me(ctx).await,
+        Some(user) if user.eq_ignore_ascii_case("me") => resolve_me(ctx).await,
+        Some(user) => match parse_username(user) {
+            Some(discord_id) => db::get_linked_user(pool, &discord_id.to_string())
+                .await?
+                .ok_or_else(|| {
+                    anyhow::anyhow!("<@{}> has not linked a tetr.io account", discord_id)
+                }),
+            None => Ok(user.to_string()),
+        },
+    }
+}
+
+async fn resolve_me(ctx: &Context<'_>) -> Result<String, Error> {
+    db::get_linked_user(&ctx.data().db_pool, &ctx.author().id.to_string())
+        .await?
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "You have not linked a tetr.io account, use `tetr link <username>` first"
+            )
+        })
+}
+
+/// Link your Discord account to a tetr.io account
+///
+/// The tetr.io account must have your Discord account connected in its settings.
+#[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
+pub async fn link(
+    ctx: Context<'_>,
+    #[description = "Your tetr username/id"] user: String,
+) -> CommandResult {
+    let user_data = ctx.data().tetr.get_user(&user).await?;
+    let author_id = ctx.author().id.to_string();
+    match user_data.connections.discord.as_ref() {
+        Some(discord) if discord.id == author_id => {
+            db::set_linked_user(&ctx.data().db_pool, &author_id, &user_data._id).await?;
+            ctx.send(|b| {
+                b.embed(|b| {
+                    b.title(format!("Linked to {}", format_user_title(&user_data)));
+                    format_tetr_user(&user_data, b)
+                })
+            })
+            .await?;
+        }
+        Some(_) => {
+            ctx.say(format!(
+                "{} is connected to a different Discord account",
+                user_data.username
+            ))
+            .await?;
+        }
+        None => {
+            ctx.say(format!(
+                "{} has no Discord account connected, connect yours in the tetr.io settings \
+                 and try again in a minute",
+                user_data.username
+            ))
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Unlink your Discord account from its tetr.io account
+#[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
+pub async fn unlink(ctx: Context<'_>) -> CommandResult {
+    match db::delete_linked_user(&ctx.data().db_pool, &ctx.author().id.to_string()).await? {
+        Some(user_id) => {
+            ctx.send(|b| {
+                b.embed(|b| {
+                    b.title("Unlinked your tetr.io account")
+                        .description(&user_id)
+                        .thumbnail(client::get_user_avatar_url(&user_id))
+                })
+            })
+            .await?;
+        }
+        None => {
+            ctx.say("You have not linked a tetr.io account").await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,227 @@
+use super::{db, db::TrackedPlayer, format_40l_time};
+use crate::{CommandResult, Context};
+
+use anyhow::Context as anyhowContext;
+use country_emoji::code_to_flag;
+use poise::serenity_prelude::{
+    self as serenity, ButtonStyle, CreateComponents, CreateEmbed, InteractionResponseType,
+};
+use std::time::Duration;
+
+const PAGE_SIZE: usize = 20;
+/// How long the buttons keep working after the last interaction
+const INTERACTION_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Copy, PartialEq)]
+enum ListSort {
+    Username,
+    Tr,
+    Sprint,
+    Blitz,
+    Playtime,
+}
+
+impl ListSort {
+    const ALL: [ListSort; 5] = [
+        ListSort::Username,
+        ListSort::Tr,
+        ListSort::Sprint,
+        ListSort::Blitz,
+        ListSort::Playtime,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            ListSort::Username => "Username",
+            ListSort::Tr => "TR",
+            ListSort::Sprint => "40L",
+            ListSort::Blitz => "Blitz",
+            ListSort::Playtime => "Playtime",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            ListSort::Username => "username",
+            ListSort::Tr => "tr",
+            ListSort::Sprint => "40l",
+            ListSort::Blitz => "blitz",
+            ListSort::Playtime => "playtime",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<ListSort> {
+        ListSort::ALL.into_iter().find(|s| s.key() == key)
+    }
+
+    /// Sorts best first, users without the stat last
+    fn sort(self, monitors: &mut [TrackedPlayer]) {
+        monitors.sort_by(|a, b| a.username.cmp(&b.username));
+        let desc = |v: Option<f64>| v.map(|v| -v).unwrap_or(f64::INFINITY);
+        let asc = |v: Option<i32>| v.map(f64::from).unwrap_or(f64::INFINITY);
+        let key = |m: &TrackedPlayer| match self {
+            ListSort::Username => 0.0,
+            ListSort::Tr => desc(m.league_rating),
+            ListSort::Sprint => asc(m.last_personal_best_40l),
+            ListSort::Blitz => desc(m.last_personal_best_blitz.map(f64::from)),
+            ListSort::Playtime => desc(Some(m.game_time)),
+        };
+        monitors.sort_by(|a, b| key(a).total_cmp(&key(b)));
+    }
+}
+
+fn format_line(position: usize, m: &TrackedPlayer, sort: ListSort) -> String {
+    let flag = code_to_flag(m.country.as_deref().unwrap_or_default()).unwrap_or_default();
+    let league = match (m.league_rank.as_deref(), m.league_rating) {
+        (Some(rank), Some(rating)) if rank != "z" => {
+            format!("{} · {:.0} TR", rank.to_uppercase(), rating)
+        }
+        _ => "Unranked".into(),
+    };
+    let mut line = format!("`#{:<3}` {} **{}** {}", position, flag, m.username, league);
+    let extra = match sort {
+        ListSort::Sprint => m.last_personal_best_40l.map(format_40l_time),
+        ListSort::Blitz => m.last_personal_best_blitz.map(|s| s.to_string()),
+        ListSort::Playtime => Some(format!("{:.1}h", m.game_time / 3600.0)),
+        ListSort::Username | ListSort::Tr => None,
+    };
+    if let Some(extra) = extra {
+        line.push_str(&format!(" · {}", extra));
+    }
+    line
+}
+
+fn num_pages(monitors: &[TrackedPlayer]) -> usize {
+    monitors.chunks(PAGE_SIZE).len()
+}
+
+fn build_embed<'a>(
+    b: &'a mut CreateEmbed,
+    monitors: &[TrackedPlayer],
+    page: usize,
+    sort: ListSort,
+) -> &'a mut CreateEmbed {
+    let start = page * PAGE_SIZE;
+    b.title(format!("{} monitored users", monitors.len()))
+        .description(
+            monitors
+                .iter()
+                .enumerate()
+                .skip(start)
+                .take(PAGE_SIZE)
+                .map(|(i, m)| format_line(i + 1, m, sort))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+        .footer(|f| {
+            f.text(format!(
+                "Page {}/{}, sorted by {}",
+                page + 1,
+                num_pages(monitors),
+                sort.label()
+            ))
+        })
+}
+
+fn build_components(
+    c: &mut CreateComponents,
+    id: u64,
+    page: usize,
+    pages: usize,
+    sort: ListSort,
+) -> &mut CreateComponents {
+    c.create_action_row(|r| {
+        r.create_select_menu(|m| {
+            m.custom_id(format!("{}-sort", id)).options(|o| {
+                for s in ListSort::ALL {
+                    o.create_option(|opt| {
+                        opt.label(format!("Sort by {}", s.label()))
+                            .value(s.key())
+                            .default_selection(s == sort)
+                    });
+                }
+                o
+            })
+        })
+    })
+    .create_action_row(|r| {
+        r.create_button(|b| {
+            b.custom_id(format!("{}-prev", id))
+                .label("Previous")
+                .style(ButtonStyle::Secondary)
+                .disabled(page == 0)
+        })
+        .create_button(|b| {
+            b.custom_id(format!("{}-next", id))
+                .label("Next")
+                .style(ButtonStyle::Secondary)
+                .disabled(page + 1 >= pages)
+        })
+    })
+}
+
+/// List tetr.io monitored users
+#[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
+pub async fn list(ctx: Context<'_>) -> CommandResult {
+    let mut monitors =
+        db::get_players_for_channel(&ctx.data().db_pool, ctx.channel_id().to_string())
+            .await
+            .context("failed to get monitored users from db")?;
+    if monitors.is_empty() {
+        ctx.say("No monitored users").await?;
+        return Ok(());
+    }
+
+    let id = ctx.id();
+    let pages = num_pages(&monitors);
+    let mut page = 0;
+    let mut sort = ListSort::Username;
+    sort.sort(&mut monitors);
+    let reply_handle = ctx
+        .send(|b| {
+            b.embed(|b| build_embed(b, &monitors, page, sort))
+                .components(|c| build_components(c, id, page, pages, sort))
+        })
+        .await?;
+
+    let prefix = format!("{}-", id);
+    while let Some(mci) = serenity::CollectComponentInteraction::new(ctx.discord())
+        .channel_id(ctx.channel_id())
+        .timeout(INTERACTION_TIMEOUT)
+        .filter({
+            let prefix = prefix.clone();
+            move |mci| mci.data.custom_id.starts_with(&prefix)
+        })
+        .await
+    {
+        match mci.data.custom_id.strip_prefix(&prefix) {
+            Some("prev") => page = page.saturating_sub(1),
+            Some("next") => page = (page + 1).min(pages - 1),
+            Some("sort") => {
+                if let Some(s) = mci.data.values.first().and_then(|v| ListSort::from_key(v)) {
+                    sort = s;
+                    sort.sort(&mut monitors);
+                    page = 0;
+                }
+            }
+            _ => {}
+        }
+        mci.create_interaction_response(ctx.discord(), |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| {
+                    d.embed(|b| build_embed(b, &monitors, page, sort))
+                        .components(|c| build_components(c, id, page, pages, sort))
+                })
+        })
+        .await?;
+    }
+
+    // Drop the buttons once they stop working
+    reply_handle
+        .edit(ctx, |b| {
+            b.embed(|b| build_embed(b, &monitors, page, sort))
+                .components(|c| c)
+        })
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use super::{autocomplete_monitored_user, db, join_lines, EMBED_FIELD_LIMIT};
+use crate::{CommandResult, Context};
+
+/// Kind of update posted for a tracked player
+#[derive(poise::ChoiceParameter, Clone, Copy, PartialEq, Debug)]
+pub enum NotifyEvent {
+    #[name = "40L personal best"]
+    PersonalBest40l,
+    #[name = "Blitz personal best"]
+    PersonalBestBlitz,
+    #[name = "League match"]
+    LeagueMatch,
+    #[name = "Rank change"]
+    RankChange,
+    #[name = "Games played"]
+    Games,
+    #[name = "Playtime milestone"]
+    Playtime,
+    #[name = "Rename"]
+    Rename,
+}
+
+impl NotifyEvent {
+    const ALL: [NotifyEvent; 7] = [
+        NotifyEvent::PersonalBest40l,
+        NotifyEvent::PersonalBestBlitz,
+        NotifyEvent::LeagueMatch,
+        NotifyEvent::RankChange,
+        NotifyEvent::Games,
+        NotifyEvent::Playtime,
+        NotifyEvent::Rename,
+    ];
+
+    /// Stable name stored in event filters
+    fn key(self) -> &'static str {
+        match self {
+            NotifyEvent::PersonalBest40l => "pb_40l",
+            NotifyEvent::PersonalBestBlitz => "pb_blitz",
+            NotifyEvent::LeagueMatch => "league_match",
+            NotifyEvent::RankChange => "rank_change",
+            NotifyEvent::Games => "games",
+            NotifyEvent::Playtime => "playtime",
+            NotifyEvent::Rename => "rename",
+        }
+    }
+
+    /// Whether the event is in a filter, `None` meaning every event
+    pub fn is_enabled(self, events: Option<&[String]>) -> bool {
+        match events {
+            Some(events) => events.iter().any(|e| e == self.key()),
+            None => true,
+        }
+    }
+}
+
+/// Filter with an event turned on or off, keeping the order of `ALL`
+fn toggle(events: Option<&[String]>, event: NotifyEvent, enabled: bool) -> Vec<String> {
+    NotifyEvent::ALL
+        .into_iter()
+        .filter(|e| {
+            if *e == event {
+                enabled
+            } else {
+                e.is_enabled(events)
+            }
+        })
+        .map(|e| e.key().to_string())
+        .collect()
+}
+
+fn describe(events: Option<&[String]>) -> String {
+    let enabled = NotifyEvent::ALL
+        .into_iter()
+        .filter(|e| e.is_enabled(events))
+        .map(|e| e.name())
+        .collect::<Vec<_>>();
+    match enabled.len() {
+        0 => "Nothing".into(),
+        n if n == NotifyEvent::ALL.len() => "Everything".into(),
+        _ => enabled.join(", "),
+    }
+}
+
+const MAX_LISTED_OVERRIDES: usize = 20;
+
+/// Choose the updates posted in this channel
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("notify_show", "notify_set", "notify_reset"),
+    guild_cooldown = 5
+)]
+pub async fn notify(ctx: Context<'_>) -> CommandResult {
+    show(ctx, None).await
+}
+
+/// Show the updates posted in this channel
+#[poise::command(prefix_command, slash_command, rename = "show", guild_cooldown = 5)]
+pub async fn notify_show(
+    ctx: Context<'_>,
+    #[description = "Tetr username/id, the whole channel by default"]
+    #[autocomplete = "autocomplete_monitored_user"]
+    user: Option<String>,
+) -> CommandResult {
+    show(ctx, user).await
+}
+
+async fn show(ctx: Context<'_>, user: Option<String>) -> CommandResult {
+    let pool = &ctx.data().db_pool;
+    let channel_id = ctx.channel_id().to_string();
+    let channel_events = db::get_channel_events(pool, &channel_id).await?;
+    if let Some(user) = user {
+        let p = match db::get_player_events(pool, &channel_id, &user).await? {
+            Some(p) => p,
+            None => {
+                ctx.say(format!("{} is not monitored in this channel", user))
+                    .await?;
+                return Ok(());
+            }
+        };
+        let (events, source) = match p.events.as_deref() {
+            Some(events) => (Some(events), "set for this player"),
+            None => (channel_events.as_deref(), "same as the channel"),
+        };
+        ctx.say(format!(
+            "Updates for **{}** ({}): {}",
+            p.username,
+            source,
+            describe(events)
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let overrides = db::get_player_events_for_channel(pool, &channel_id).await?;
+    ctx.send(|b| {
+        b.embed(|b| {
+            b.title("Posted updates")
+                .description(describe(channel_events.as_deref()));
+            if !overrides.is_empty() {
+                let lines = overrides
+                    .iter()
+                    .map(|p| format!("**{}**: {}", p.username, describe(p.events.as_deref())))
+                    .collect::<Vec<_>>();
+                b.field(
+                    "Players with their own updates",
+                    join_lines(&lines, MAX_LISTED_OVERRIDES, EMBED_FIELD_LIMIT),
+                    false,
+                );
+            }
+            b
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// Turn an update on or off in this channel, or for one player in it
+#[poise::command(
+    prefix_command,
+    slash_command,
+    rename = "set",
+    guild_cooldown = 5,
+    required_permissions = "MANAGE_CHANNELS"
+)]
+pub async fn notify_set(
+    ctx: Context<'_>,
+    #[description = "Update to turn on or off"] event: NotifyEvent,
+    #[description = "Whether to post it"] enabled: bool,
+    #[description = "Tetr username/id, the whole channel by default"]
+    #[autocomplete = "autocomplete_monitored_user"]
+    user: Option<String>,
+) -> CommandResult {
+    let pool = &ctx.data().db_pool;
+    let channel_id = ctx.channel_id().to_string();
+    let channel_events = db::get_channel_events(pool, &channel_id).await?;
+    let state = if enabled { "on" } else { "off" };
+    match user {
+        Some(user) => {
+            let p = match db::get_player_events(pool, &channel_id, &user).await? {
+                Some(p) => p,
+                None => {
+                    ctx.say(format!("{} is not monitored in this channel", user))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            // A player without their own updates starts from the channel's
+            let current = p.events.as_deref().or(channel_events.as_deref());
+            let events = toggle(current, event, enabled);
+            db::set_player_events(pool, &channel_id, &p.user_id, Some(&events)).await?;
+            ctx.say(format!(
+                "{} updates for **{}** turned {}",
+                event.name(),
+                p.username,
+                state
+            ))
+            .await?;
+        }
+        None => {
+            let events = toggle(channel_events.as_deref(), event, enabled);
+            db::set_channel_events(pool, &channel_id, Some(&events)).await?;
+            ctx.say(format!("{} updates turned {}", event.name(), state))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Post every update in this channel again, or use the channel's for a player
+#[poise::command(
+    prefix_command,
+    slash_command,
+    rename = "reset",
+    guild_cooldown = 5,
+    required_permissions = "MANAGE_CHANNELS"
+)]
+pub async fn notify_reset(
+    ctx: Context<'_>,
+    #[description = "Tetr username/id, the whole channel by default"]
+    #[autocomplete = "autocomplete_monitored_user"]
+    user: Option<String>,
+) -> CommandResult {
+    let pool = &ctx.data().db_pool;
+    let channel_id = ctx.channel_id().to_string();
+    match user {
+        Some(user) => {
+            let p = match db::get_player_events(pool, &channel_id, &user).await? {
+                Some(p) => p,
+                None => {
+                    ctx.say(format!("{} is not monitored in this channel", user))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            db::set_player_events(pool, &channel_id, &p.user_id, None).await?;
+            ctx.say(format!(
+                "**{}** now gets the same updates as the channel",
+                p.username
+            ))
+            .await?;
+        }
+        None => {
+            db::set_channel_events(pool, &channel_id, None).await?;
+            ctx.say("Every update is posted again, players with their own updates keep them")
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles_events() {
+        let all = toggle(None, NotifyEvent::Rename, true);
+        assert_eq!(all.len(), NotifyEvent::ALL.len());
+
+        let no_rename = toggle(None, NotifyEvent::Rename, false);
+        assert!(!NotifyEvent::Rename.is_enabled(Some(&no_rename)));
+        assert!(NotifyEvent::LeagueMatch.is_enabled(Some(&no_rename)));
+
+        let pbs = ["pb_blitz".to_string(), "pb_40l".to_string()];
+        assert_eq!(
+            toggle(Some(&pbs), NotifyEvent::RankChange, true),
+            vec!["pb_40l", "pb_blitz", "rank_change"]
+        );
+        assert_eq!(describe(Some(&[][..])), "Nothing");
+        assert_eq!(describe(None), "Everything");
+    }
+}
@@ -0,0 +1,721 @@
+use super::{
+    client, db,
+    db::{Subscription, TrackedPlayer},
+    notify::NotifyEvent,
+};
+use crate::{Data, Error};
+
+use anyhow::Context as anyhowContext;
+use futures::{stream, StreamExt};
+use poise::serenity_prelude::{
+    constants::{EMBED_MAX_COUNT, EMBED_MAX_LENGTH},
+    ChannelId, CreateEmbed, Http,
+};
+use pretty_duration::pretty_duration;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Locks of the players being refreshed, so that the poller and the refresh
+/// command never announce the same change twice
+pub type RefreshLocks = Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>;
+
+fn games_embed(user: &client::TetrUser, m: &TrackedPlayer) -> CreateEmbed {
+    let new_games = user.gamesplayed - m.games_played;
+    let new_time = Duration::from_secs((user.gametime - m.game_time).max(0.0) as u64);
+    let mut b = CreateEmbed::default();
+    b.title(format!("{} played {} new games", user.username, new_games))
+        .field(
+            "Play time",
+            format!("+{}", pretty_duration(&new_time, None)),
+            true,
+        )
+        .field("Online games", user.gamesplayed, true)
+        .thumbnail(client::get_user_avatar_url(&user._id));
+    b
+}
+
+/// Highest multiple of `every_hours` reached between two play times in
+/// seconds, `None` if none was crossed
+fn playtime_milestone(old_secs: f64, new_secs: f64, every_hours: u64) -> Option<u64> {
+    let step = every_hours as f64 * 3600.0;
+    let reached = (new_secs / step).floor();
+    (reached > (old_secs / step).floor() && reached >= 1.0).then(|| reached as u64 * every_hours)
+}
+
+fn milestone_embed(user: &client::TetrUser, hours: u64) -> CreateEmbed {
+    let mut b = CreateEmbed::default();
+    b.title(format!(
+        "{} reached {} hours of play time!",
+        user.username, hours
+    ))
+    .color((0, 150, 255))
+    .field("Online games", user.gamesplayed, true)
+    .field("Games won", user.gameswon, true)
+    .thumbnail(client::get_user_avatar_url(&user._id));
+    b
+}
+
+fn personal_best_embed(
+    user: &client::TetrUser,
+    mode: &str,
+    old: String,
+    new: String,
+    delta: String,
+) -> CreateEmbed {
+    let mut b = CreateEmbed::default();
+    b.title(format!("New {} PB for {}!", mode, user.username))
+        .color((255, 215, 0))
+        .field("New", new, true)
+        .field("Old", old, true)
+        .field("Delta", delta, true)
+        .thumbnail(client::get_user_avatar_url(&user._id));
+    b
+}
+
+fn league_embed(
+    user: &client::TetrUser,
+    m: &TrackedPlayer,
+    updated: &TrackedPlayer,
+    tr_change_threshold: f64,
+) -> Option<CreateEmbed> {
+    let league = &user.league;
+    let old_rank = m.league_rank.as_deref().and_then(client::rank_index);
+    let new_rank = league.rank_index();
+    let title = match (old_rank, new_rank) {
+        (Some(old), Some(new)) if new > old => format!(
+            "{} ranked up to {}!",
+            user.username,
+            league.rank.to_uppercase()
+        ),
+        (Some(old), Some(new)) if new < old => format!(
+            "{} ranked down to {}",
+            user.username,
+            league.rank.to_uppercase()
+        ),
+        _ => match (updated.last_notified_rating, updated.league_rating) {
+            (Some(old), Some(new)) if (new - old).abs() >= tr_change_threshold => format!(
+                "{} {} {:.0} TR",
+                user.username,
+                if new > old { "gained" } else { "lost" },
+                (new - old).abs()
+            ),
+            _ => return None,
+        },
+    };
+
+    let mut b = CreateEmbed::default();
+    b.title(title)
+        .thumbnail(client::get_user_avatar_url(&user._id));
+    let old_rating = m.last_notified_rating.or(m.league_rating);
+    match (old_rating, updated.league_rating) {
+        (Some(old), Some(new)) => {
+            b.color(if new >= old { (0, 200, 0) } else { (200, 0, 0) })
+                .field(
+                    "TR",
+                    format!("{:.2} → {:.2} ({:+.2})", old, new, new - old),
+                    false,
+                );
+        }
+        (_, Some(new)) => {
+            b.field("TR", format!("{:.2}", new), false);
+        }
+        _ => {}
+    }
+    if let (Some(glicko), Some(rd)) = (league.glicko, league.rd) {
+        b.field("Glicko", format!("{:.2} ± {:.2}", glicko, rd), true);
+    }
+    if let Some(standing) = league.standing.filter(|s| *s > 0) {
+        b.field("Standing", format!("#{}", standing), true);
+    }
+    if let Some(percentile) = league.percentile {
+        b.field("Top", format!("{:.2}%", percentile * 100.0), true);
+    }
+    if let (Some(apm), Some(pps), Some(vs)) = (league.apm, league.pps, league.vs) {
+        b.field(
+            "APM / PPS / VS",
+            format!("{:.2} / {:.2} / {:.2}", apm, pps, vs),
+            false,
+        );
+    }
+    Some(b)
+}
+
+fn rename_embed(user: &client::TetrUser, old_username: &str) -> CreateEmbed {
+    let mut b = CreateEmbed::default();
+    b.title(format!(
+        "{} is now known as {}",
+        old_username, user.username
+    ))
+    .thumbnail(client::get_user_avatar_url(&user._id));
+    b
+}
+
+fn format_round_stats(p: &client::MultiplayerEndContext, round: usize) -> String {
+    let points = &p.points;
+    format!(
+        "{:>6.2} {:>4.2} {:>6.2}",
+        points.secondary_avg_tracking.get(round).unwrap_or(&0.0),
+        points.tertiary_avg_tracking.get(round).unwrap_or(&0.0),
+        points.extra_avg_tracking.vs.get(round).unwrap_or(&0.0),
+    )
+}
+
+fn match_embed(
+    user: &client::TetrUser,
+    record: &client::TetrLeagueRecord,
+    tr_change: Option<f64>,
+) -> Option<CreateEmbed> {
+    let (me, opponent) = record.players(&user._id)?;
+    let mut b = CreateEmbed::default();
+    b.title(format!(
+        "{} {} against {} {} - {}",
+        user.username,
+        if me.success { "won" } else { "lost" },
+        opponent.user.username,
+        me.wins,
+        opponent.wins
+    ))
+    .url(client::get_replay_url(&record.replayid))
+    .color(if me.success { (0, 200, 0) } else { (200, 0, 0) })
+    .thumbnail(client::get_user_avatar_url(&opponent.user._id))
+    .field(
+        "APM / PPS / VS",
+        format!(
+            "{:.2} / {:.2} / {:.2}\nvs {:.2} / {:.2} / {:.2}",
+            me.points.apm(),
+            me.points.pps(),
+            me.points.vs(),
+            opponent.points.apm(),
+            opponent.points.pps(),
+            opponent.points.vs()
+        ),
+        true,
+    );
+    if let Some(tr_change) = tr_change {
+        b.field("TR", format!("{:+.2}", tr_change), true);
+    }
+
+    let rounds = me.points.secondary_avg_tracking.len();
+    if rounds > 0 {
+        let mut table = format!("{:>3} {:^18} | {:^18}\n", "#", "APM PPS VS", "Opponent");
+        for round in 0..rounds {
+            table += &format!(
+                "{:>3} {} | {}\n",
+                round + 1,
+                format_round_stats(me, round),
+                format_round_stats(opponent, round)
+            );
+        }
+        b.field("Rounds", format!("```\n{}```", table), false);
+    }
+    Some(b)
+}
+
+/// Matches newer than the last one seen, oldest first, with the TR change to
+/// show on them. Nothing is new until a match was seen, and every match is new
+/// if the last one seen is not among them anymore.
+fn new_matches<'a>(
+    p: &TrackedPlayer,
+    updated: &TrackedPlayer,
+    matches: &'a [client::TetrLeagueRecord],
+) -> (Vec<&'a client::TetrLeagueRecord>, Option<f64>) {
+    let last_match_id = match p.last_match_id.as_deref() {
+        Some(id) => id,
+        None => return (vec![], None),
+    };
+    let mut new_matches = matches
+        .iter()
+        .take_while(|r| r._id != last_match_id)
+        .collect::<Vec<_>>();
+    new_matches.reverse();
+    // TR is only known before and after the whole batch
+    let tr_change = match (p.league_rating, updated.league_rating) {
+        (Some(old), Some(new)) if new_matches.len() == 1 => Some(new - old),
+        _ => None,
+    };
+    (new_matches, tr_change)
+}
+
+/// Fetches the latest data for a tracked player, posts embeds to every
+/// subscribed channel for everything that changed and that the channel wants,
+/// and stores the new state.
+///
+/// Returns whether an update was posted.
+pub async fn refresh_player(
+    http: &Http,
+    data: &Data,
+    p: &TrackedPlayer,
+    subscriptions: &[Subscription],
+) -> Result<bool, Error> {
+    let lock = data
+        .refresh_locks
+        .lock()
+        .unwrap()
+        .entry(p.user_id.clone())
+        .or_default()
+        .clone();
+    let result = {
+        let _guard = lock.lock().await;
+        refresh_locked_player(http, data, &p.user_id, subscriptions).await
+    };
+    // Forget the lock once no other refresh of the player holds it, checked
+    // with the map locked so that nobody can clone it in the meantime
+    let mut locks = data.refresh_locks.lock().unwrap();
+    if Arc::strong_count(&lock) == 2 {
+        locks.remove(&p.user_id);
+    }
+    result
+}
+
+async fn refresh_locked_player(
+    http: &Http,
+    data: &Data,
+    user_id: &str,
+    subscriptions: &[Subscription],
+) -> Result<bool, Error> {
+    // Another refresh may have stored a newer state while this one waited
+    let p = &match db::get_player(&data.db_pool, user_id).await? {
+        Some(p) => p,
+        None => return Ok(false),
+    };
+
+    let (user, record, matches) = tokio::try_join!(
+        data.tetr.get_user(&p.user_id),
+        data.tetr.get_user_record(&p.user_id),
+        data.tetr.get_user_league_recent(&p.user_id)
+    )
+    .context("failed to get user data")?;
+
+    let mut updated = TrackedPlayer {
+        game_time: user.gametime,
+        games_played: user.gamesplayed,
+        games_won: user.gameswon,
+        country: user.country.clone(),
+        discord_id: user.connections.discord.as_ref().map(|d| d.id.clone()),
+        ..p.clone()
+    };
+    super::set_league_standing(&mut updated, &user.league);
+
+    let mut embeds = vec![];
+    if user.username != p.username {
+        db::insert_rename(&data.db_pool, &p.user_id, &p.username, &user.username).await?;
+        updated.username = user.username.clone();
+        if data.config.tetr.announce_renames {
+            embeds.push((NotifyEvent::Rename, rename_embed(&user, &p.username)));
+        }
+    }
+    if user.gamesplayed != p.games_played || user.gametime != p.game_time {
+        embeds.push((NotifyEvent::Games, games_embed(&user, p)));
+    }
+    if let Some(hours) = playtime_milestone(
+        p.game_time,
+        user.gametime,
+        data.config.tetr.playtime_milestone_hours,
+    ) {
+        embeds.push((NotifyEvent::Playtime, milestone_embed(&user, hours)));
+    }
+
+    // A PB unknown until now is only stored, it may have been set long ago
+    if let Some(new) = record.best_40l_ms() {
+        match p.last_personal_best_40l {
+            Some(old) if old <= new => {}
+            None => updated.last_personal_best_40l = Some(new),
+            Some(old) => {
+                embeds.push((
+                    NotifyEvent::PersonalBest40l,
+                    personal_best_embed(
+                        &user,
+                        "40L",
+                        super::format_40l_time(old),
+                        super::format_40l_time(new),
+                        format!("-{}", super::format_40l_time(old - new)),
+                    ),
+                ));
+                updated.last_personal_best_40l = Some(new);
+            }
+        }
+    }
+
+    if let Some(new) = record.best_blitz_score() {
+        match p.last_personal_best_blitz {
+            Some(old) if old >= new => {}
+            None => updated.last_personal_best_blitz = Some(new),
+            Some(old) => {
+                embeds.push((
+                    NotifyEvent::PersonalBestBlitz,
+                    personal_best_embed(
+                        &user,
+                        "Blitz",
+                        old.to_string(),
+                        new.to_string(),
+                        format!("+{}", new - old),
+                    ),
+                ));
+                updated.last_personal_best_blitz = Some(new);
+            }
+        }
+    }
+
+    if let Some(embed) = league_embed(&user, p, &updated, data.config.tetr.tr_change_threshold) {
+        embeds.push((NotifyEvent::RankChange, embed));
+        updated.last_notified_rating = updated.league_rating;
+    } else if updated.last_notified_rating.is_none() || updated.league_rating.is_none() {
+        updated.last_notified_rating = updated.league_rating;
+    }
+
+    let (new_matches, tr_change) = new_matches(p, &updated, &matches);
+    for r in new_matches {
+        embeds.extend(match_embed(&user, r, tr_change).map(|e| (NotifyEvent::LeagueMatch, e)));
+    }
+    if let Some(latest) = matches.first() {
+        updated.last_match_id = Some(latest._id.clone());
+    }
+
+    let mut posted = false;
+    for sub in subscriptions {
+        let sub_embeds = embeds
+            .iter()
+            .filter(|(event, _)| event.is_enabled(sub.events.as_deref()))
+            .map(|(_, embed)| embed.clone())
+            .collect::<Vec<_>>();
+        if sub_embeds.is_empty() {
+            continue;
+        }
+        posted = true;
+        // A channel that cannot be posted to should not block the others
+        if let Err(why) = post_update(http, data, sub, &sub_embeds).await {
+            println!(
+                "error posting update for {} to {}: {:?}",
+                p.user_id, sub.channel_id, why
+            );
+        }
+    }
+
+    if updated != *p {
+        db::update_player(&data.db_pool, &updated).await?;
+    }
+    // Players may also be refreshed by the refresh command between polls
+    db::insert_snapshot(
+        &data.db_pool,
+        &snapshot_of(&user, &updated),
+        data.config.tetr.poll_interval_secs as i64 / 2,
+    )
+    .await?;
+    Ok(posted)
+}
+
+async fn post_update(
+    http: &Http,
+    data: &Data,
+    sub: &Subscription,
+    embeds: &[CreateEmbed],
+) -> Result<(), Error> {
+    let channel_id = ChannelId(
+        sub.channel_id
+            .parse()
+            .with_context(|| format!("invalid channel id {}", sub.channel_id))?,
+    );
+    for chunk in split_messages(embeds) {
+        let message = channel_id
+            .send_message(http, |b| b.set_embeds(chunk.to_vec()))
+            .await
+            .context("failed to send update message")?;
+        if let (None, Some(guild_id)) = (&sub.guild_id, message.guild_id) {
+            db::set_subscription_guild(&data.db_pool, &sub.channel_id, &guild_id.to_string())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Characters of an embed counted towards the limit of a message
+fn embed_length(embed: &CreateEmbed) -> usize {
+    let text = |v: Option<&Value>| v.and_then(Value::as_str).map_or(0, |s| s.chars().count());
+    let e = &embed.0;
+    let fields = e
+        .get("fields")
+        .and_then(Value::as_array)
+        .map_or(0, |fields| {
+            fields
+                .iter()
+                .map(|f| text(f.get("name")) + text(f.get("value")))
+                .sum()
+        });
+    text(e.get("title"))
+        + text(e.get("description"))
+        + text(e.get("footer").and_then(|f| f.get("text")))
+        + text(e.get("author").and_then(|a| a.get("name")))
+        + fields
+}
+
+/// Groups embeds into as few messages as Discord accepts, in order
+fn split_messages(embeds: &[CreateEmbed]) -> Vec<&[CreateEmbed]> {
+    let mut messages = vec![];
+    let (mut start, mut length) = (0, 0);
+    for (i, embed) in embeds.iter().enumerate() {
+        let embed_length = embed_length(embed);
+        if i > start && (i - start == EMBED_MAX_COUNT || length + embed_length > EMBED_MAX_LENGTH) {
+            messages.push(&embeds[start..i]);
+            start = i;
+            length = 0;
+        }
+        length += embed_length;
+    }
+    if start < embeds.len() {
+        messages.push(&embeds[start..]);
+    }
+    messages
+}
+
+fn snapshot_of(user: &client::TetrUser, p: &TrackedPlayer) -> db::UserSnapshot {
+    db::UserSnapshot {
+        id: 0,
+        user_id: user._id.clone(),
+        created_at: chrono::Utc::now(),
+        league_rating: p.league_rating,
+        league_rank: p.league_rank.clone(),
+        personal_best_40l: p.last_personal_best_40l,
+        personal_best_blitz: p.last_personal_best_blitz,
+        game_time: user.gametime,
+        games_played: user.gamesplayed,
+        games_won: user.gameswon,
+        xp: user.xp,
+    }
+}
+
+async fn refresh_all(http: &Http, data: &Data) -> Result<(), Error> {
+    let start = std::time::Instant::now();
+    let players = db::get_all_players(&data.db_pool)
+        .await
+        .context("failed to get tracked players from db")?;
+    let mut subscriptions = HashMap::<_, Vec<_>>::new();
+    for sub in db::get_all_subscriptions(&data.db_pool).await? {
+        subscriptions
+            .entry(sub.user_id.clone())
+            .or_default()
+            .push(sub);
+    }
+    let results = stream::iter(players)
+        .map(|p| {
+            let subs = subscriptions.get(&p.user_id).map_or(&[][..], Vec::as_slice);
+            async move { (refresh_player(http, data, &p, subs).await, p) }
+        })
+        .buffer_unordered(data.config.tetr.max_concurrent_requests)
+        .collect::<Vec<_>>()
+        .await;
+    let mut num_updated = 0;
+    for (result, p) in results.iter() {
+        match result {
+            Ok(true) => num_updated += 1,
+            Ok(false) => {}
+            Err(why) => println!("error during poll for {}: {:?}", p.user_id, why),
+        }
+    }
+    println!(
+        "Polled {} players, {} updated in {:?}",
+        results.len(),
+        num_updated,
+        start.elapsed()
+    );
+
+    if let Err(why) = super::roles::sync_all_roles(http, data).await {
+        println!("error during role sync: {:?}", why);
+    }
+    Ok(())
+}
+
+/// Spawns a task that refreshes every monitor in the database every
+/// configured poll interval.
+pub fn spawn_poller(http: Arc<Http>, data: Data) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(data.config.tetr.poll_interval());
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(why) = refresh_all(&http, &data).await {
+                println!("error during poll: {:?}", why);
+            }
+        }
+    });
+}
+
+/// Snapshots are downsampled to one a day, so pruning them more often than
+/// daily only rescans the table
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Spawns a task that prunes old snapshots on startup and then every day.
+pub fn spawn_snapshot_pruner(data: Data) {
+    tokio::spawn(async move {
+        let snapshots = &data.config.snapshots;
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match db::prune_snapshots(
+                &data.db_pool,
+                snapshots.full_resolution_days,
+                snapshots.retention_days,
+            )
+            .await
+            {
+                Ok(0) => {}
+                Ok(pruned) => println!("Pruned {} snapshots", pruned),
+                Err(why) => println!("error pruning snapshots: {:?}", why),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player() -> TrackedPlayer {
+        super::super::new_player(&client::fixture_user(), &client::fixture_user_record())
+    }
+
+    fn title(e: &CreateEmbed) -> &str {
+        e.0["title"].as_str().unwrap()
+    }
+
+    fn field<'a>(e: &'a CreateEmbed, name: &str) -> Option<&'a str> {
+        e.0.get("fields")?
+            .as_array()?
+            .iter()
+            .find(|f| f["name"] == name)?["value"]
+            .as_str()
+    }
+
+    #[test]
+    fn announces_rank_changes() {
+        let user = client::fixture_user();
+        let mut old = player();
+        old.league_rank = Some("u".into());
+        let embed = league_embed(&user, &old, &player(), 100.0).unwrap();
+        assert_eq!(title(&embed), "osk ranked up to X!");
+
+        let mut user = client::fixture_user();
+        user.league.rank = "u".into();
+        let embed = league_embed(&user, &player(), &player(), 100.0).unwrap();
+        assert_eq!(title(&embed), "osk ranked down to U");
+    }
+
+    #[test]
+    fn announces_tr_changes_from_last_announced_rating() {
+        let user = client::fixture_user();
+        assert!(league_embed(&user, &player(), &player(), 100.0).is_none());
+
+        // Small changes since the last announcement add up
+        let mut old = player();
+        old.league_rating = Some(24826.81);
+        old.last_notified_rating = Some(24756.81);
+        let updated = TrackedPlayer {
+            league_rating: Some(24876.81),
+            ..old.clone()
+        };
+        let embed = league_embed(&user, &old, &updated, 100.0).unwrap();
+        assert_eq!(title(&embed), "osk gained 120 TR");
+        assert_eq!(field(&embed, "TR"), Some("24756.81 → 24876.81 (+120.00)"));
+        assert!(league_embed(&user, &old, &updated, 150.0).is_none());
+
+        let updated = TrackedPlayer {
+            league_rating: Some(24606.81),
+            ..old.clone()
+        };
+        let embed = league_embed(&user, &old, &updated, 100.0).unwrap();
+        assert_eq!(title(&embed), "osk lost 150 TR");
+    }
+
+    #[test]
+    fn describes_matches() {
+        let user = client::fixture_user();
+        let matches = client::fixture_league_recent();
+        let embed = match_embed(&user, &matches[0], Some(12.5)).unwrap();
+        assert!(title(&embed).starts_with("osk won against"));
+        assert_eq!(field(&embed, "TR"), Some("+12.50"));
+        assert!(field(&embed, "Rounds").is_some());
+        assert!(field(&match_embed(&user, &matches[0], None).unwrap(), "TR").is_none());
+
+        let mut stranger = client::fixture_user();
+        stranger._id = "0".repeat(24);
+        assert!(match_embed(&stranger, &matches[0], None).is_none());
+    }
+
+    #[test]
+    fn finds_new_matches() {
+        let matches = client::fixture_league_recent();
+        let ids = |(new, _): (Vec<&client::TetrLeagueRecord>, Option<f64>)| {
+            new.iter().map(|r| r._id.clone()).collect::<Vec<_>>()
+        };
+        let updated = player();
+
+        // Nothing is announced before the first match is seen
+        let mut p = player();
+        assert!(ids(new_matches(&p, &updated, &matches)).is_empty());
+
+        p.last_match_id = Some(matches[0]._id.clone());
+        assert!(ids(new_matches(&p, &updated, &matches)).is_empty());
+
+        p.last_match_id = Some(matches[1]._id.clone());
+        p.league_rating = Some(24860.0);
+        let (new, tr_change) = new_matches(&p, &updated, &matches);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0]._id, matches[0]._id);
+        assert!((tr_change.unwrap() - 16.81).abs() < 1e-6);
+
+        // The last seen match fell out of the recent list
+        p.last_match_id = Some("f".repeat(24));
+        let (new, tr_change) = new_matches(&p, &updated, &matches);
+        assert_eq!(
+            new.iter().map(|r| &r._id).collect::<Vec<_>>(),
+            vec![&matches[1]._id, &matches[0]._id]
+        );
+        assert_eq!(tr_change, None);
+    }
+
+    fn embed_of_length(length: usize) -> CreateEmbed {
+        let mut b = CreateEmbed::default();
+        b.title("t").field("f", "x".repeat(length - 2), false);
+        b
+    }
+
+    #[test]
+    fn detects_playtime_milestones() {
+        let hours = |h: f64| h * 3600.0;
+        assert_eq!(
+            playtime_milestone(hours(99.5), hours(100.2), 100),
+            Some(100)
+        );
+        assert_eq!(playtime_milestone(hours(100.2), hours(150.0), 100), None);
+        assert_eq!(
+            playtime_milestone(hours(150.0), hours(320.0), 100),
+            Some(300)
+        );
+        assert_eq!(playtime_milestone(0.0, hours(50.0), 100), None);
+        assert_eq!(playtime_milestone(hours(120.0), hours(110.0), 100), None);
+    }
+
+    #[test]
+    fn splits_messages_by_count_and_length() {
+        assert_eq!(embed_length(&embed_of_length(600)), 600);
+
+        let embeds = (0..12).map(|_| embed_of_length(100)).collect::<Vec<_>>();
+        let sizes = split_messages(&embeds)
+            .iter()
+            .map(|m| m.len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![10, 2]);
+
+        let embeds = (0..11).map(|_| embed_of_length(700)).collect::<Vec<_>>();
+        let sizes = split_messages(&embeds)
+            .iter()
+            .map(|m| m.len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![8, 3]);
+        assert!(split_messages(&[]).is_empty());
+    }
+}
@@ -0,0 +1,447 @@
+use super::{client, db, http_status, join_lines, EMBED_DESCRIPTION_LIMIT};
+use crate::{CommandResult, Context, Data, Error};
+
+use anyhow::Context as anyhowContext;
+use poise::serenity_prelude::{self as serenity, GuildId, Http, RoleId, UserId};
+use std::collections::{HashMap, HashSet};
+
+const AUDIT_LOG_REASON: &str = "Tetra League rank changed";
+const MAX_PREVIEW_CHANGES: usize = 30;
+
+/// Roles to add to and remove from a member so they match their rank
+pub struct RoleChange {
+    pub username: String,
+    pub discord_id: UserId,
+    pub add: Option<RoleId>,
+    pub remove: Vec<RoleId>,
+    /// Why the change could not be applied
+    pub error: Option<String>,
+}
+
+/// Normalized rank letter, `None` if it is not a rank a role can be given for
+fn parse_rank(rank: &str) -> Option<&'static str> {
+    let rank = rank.trim().to_lowercase();
+    client::LEAGUE_RANKS[1..]
+        .iter()
+        .find(|r| **r == rank)
+        .copied()
+}
+
+async fn autocomplete_rank(_ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let partial = partial.to_lowercase();
+    client::LEAGUE_RANKS[1..]
+        .iter()
+        .rev()
+        .filter(|r| r.starts_with(&partial))
+        .map(|r| r.to_uppercase())
+        .collect()
+}
+
+fn describe_role_error(e: &serenity::Error) -> String {
+    match http_status(e) {
+        Some(403) => {
+            "missing permissions, the bot needs Manage Roles and a role above the rank roles".into()
+        }
+        _ => e.to_string(),
+    }
+}
+
+async fn apply_change(http: &Http, guild_id: GuildId, change: &RoleChange) -> serenity::Result<()> {
+    for role_id in change.remove.iter() {
+        http.remove_member_role(
+            guild_id.0,
+            change.discord_id.0,
+            role_id.0,
+            Some(AUDIT_LOG_REASON),
+        )
+        .await?;
+    }
+    if let Some(role_id) = change.add {
+        http.add_member_role(
+            guild_id.0,
+            change.discord_id.0,
+            role_id.0,
+            Some(AUDIT_LOG_REASON),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Rank role change of a member, `None` if they already have the right roles.
+/// `wanted` is the only rank role they should have.
+async fn member_change(
+    http: &Http,
+    guild_id: GuildId,
+    username: &str,
+    discord_id: UserId,
+    wanted: Option<RoleId>,
+    managed_roles: &HashSet<RoleId>,
+) -> serenity::Result<Option<RoleChange>> {
+    let member = match http.get_member(guild_id.0, discord_id.0).await {
+        Ok(member) => member,
+        Err(e) if http_status(&e) == Some(404) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let add = wanted.filter(|r| !member.roles.contains(r));
+    let remove = member
+        .roles
+        .iter()
+        .filter(|r| managed_roles.contains(r) && Some(**r) != wanted)
+        .copied()
+        .collect::<Vec<_>>();
+    if add.is_none() && remove.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(RoleChange {
+        username: username.to_string(),
+        discord_id,
+        add,
+        remove,
+        error: None,
+    }))
+}
+
+/// Computes the rank role changes of every player followed in a guild whose
+/// tetr.io account has a Discord account connected, from the rank and account
+/// stored by the last refresh, and applies them unless `dry_run`. Outside a dry
+/// run, members are only fetched when their rank role or account changed since
+/// the last sync. Changes that fail, or of members that could not be fetched,
+/// are returned with an error.
+pub async fn sync_guild_roles(
+    http: &Http,
+    data: &Data,
+    guild_id: GuildId,
+    dry_run: bool,
+) -> Result<Vec<RoleChange>, Error> {
+    let pool = &data.db_pool;
+    let guild_key = guild_id.to_string();
+    let mut rank_roles = HashMap::new();
+    for r in db::get_rank_roles(pool, &guild_key).await? {
+        let role_id = r
+            .role_id
+            .parse()
+            .with_context(|| format!("invalid role id {}", r.role_id))?;
+        rank_roles.insert(r.rank, RoleId(role_id));
+    }
+    if rank_roles.is_empty() {
+        return Ok(vec![]);
+    }
+    let managed_roles = rank_roles.values().copied().collect::<HashSet<_>>();
+    let synced = db::get_rank_role_syncs(pool, &guild_key)
+        .await?
+        .into_iter()
+        .map(|s| (s.user_id.clone(), s))
+        .collect::<HashMap<_, _>>();
+
+    let mut changes = vec![];
+    for p in db::get_players_for_guild(pool, &guild_key).await? {
+        let wanted = p
+            .league_rank
+            .as_ref()
+            .and_then(|rank| rank_roles.get(rank))
+            .copied();
+        let sync = p.discord_id.as_ref().map(|discord_id| db::RankRoleSync {
+            guild_id: guild_key.clone(),
+            user_id: p.user_id.clone(),
+            discord_id: discord_id.clone(),
+            role_id: wanted.map(|r| r.to_string()),
+        });
+        let previous = synced.get(&p.user_id);
+        if !dry_run && previous == sync.as_ref() {
+            continue;
+        }
+
+        // An account that is no longer connected loses the rank roles
+        let mut targets = vec![];
+        if let Some(previous) =
+            previous.filter(|prev| Some(&prev.discord_id) != p.discord_id.as_ref())
+        {
+            targets.push((previous.discord_id.as_str(), None));
+        }
+        if let Some(discord_id) = p.discord_id.as_deref() {
+            targets.push((discord_id, wanted));
+        }
+        let mut failed = false;
+        for (discord_id, wanted) in targets {
+            let discord_id = match discord_id.parse() {
+                Ok(id) => UserId(id),
+                Err(_) => continue,
+            };
+            let change = match member_change(
+                http,
+                guild_id,
+                &p.username,
+                discord_id,
+                wanted,
+                &managed_roles,
+            )
+            .await
+            {
+                Ok(change) => change,
+                Err(e) => {
+                    failed = true;
+                    changes.push(RoleChange {
+                        username: p.username.clone(),
+                        discord_id,
+                        add: wanted,
+                        remove: vec![],
+                        error: Some(format!("failed to get member: {}", describe_role_error(&e))),
+                    });
+                    continue;
+                }
+            };
+            if let Some(mut change) = change {
+                if !dry_run {
+                    if let Err(e) = apply_change(http, guild_id, &change).await {
+                        change.error = Some(describe_role_error(&e));
+                        failed = true;
+                    }
+                }
+                changes.push(change);
+            }
+        }
+
+        // Failed changes are retried on the next sync
+        if !dry_run && !failed {
+            match sync {
+                Some(sync) => db::set_rank_role_sync(pool, &sync).await?,
+                None => db::delete_rank_role_sync(pool, &guild_key, &p.user_id).await?,
+            }
+        }
+    }
+    Ok(changes)
+}
+
+/// Applies rank roles in every guild that has any
+pub async fn sync_all_roles(http: &Http, data: &Data) -> Result<(), Error> {
+    for guild_id in db::get_rank_role_guilds(&data.db_pool).await? {
+        let guild_id = GuildId(
+            guild_id
+                .parse()
+                .with_context(|| format!("invalid guild id {}", guild_id))?,
+        );
+        match sync_guild_roles(http, data, guild_id, false).await {
+            Ok(changes) => {
+                for c in changes.iter() {
+                    if let Some(error) = c.error.as_deref() {
+                        println!(
+                            "failed to update roles of {} in {}: {}",
+                            c.discord_id, guild_id, error
+                        );
+                    }
+                }
+            }
+            Err(why) => println!("error during role sync for {}: {:?}", guild_id, why),
+        }
+    }
+    Ok(())
+}
+
+/// Roles the bot cannot give because they are not below its highest role
+async fn unmanageable_roles(
+    ctx: &Context<'_>,
+    guild_id: GuildId,
+) -> Result<HashSet<RoleId>, Error> {
+    let http = &ctx.discord().http;
+    let roles = guild_id.roles(http).await?;
+    let bot = http
+        .get_member(guild_id.0, ctx.discord().cache.current_user_id().0)
+        .await?;
+    let highest = bot
+        .roles
+        .iter()
+        .filter_map(|r| roles.get(r))
+        .map(|r| r.position)
+        .max()
+        .unwrap_or(0);
+    Ok(roles
+        .values()
+        .filter(|r| r.position >= highest)
+        .map(|r| r.id)
+        .collect())
+}
+
+fn guild_id_of(ctx: &Context<'_>) -> Result<GuildId, Error> {
+    ctx.guild_id()
+        .ok_or_else(|| anyhow::anyhow!("not in a guild"))
+}
+
+/// Give roles to monitored users by Tetra League rank
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("roles_show", "roles_set", "roles_unset", "roles_preview"),
+    guild_only,
+    required_permissions = "MANAGE_ROLES"
+)]
+pub async fn roles(ctx: Context<'_>) -> CommandResult {
+    show(ctx).await
+}
+
+/// Show the roles given for Tetra League ranks
+#[poise::command(
+    prefix_command,
+    slash_command,
+    rename = "show",
+    guild_only,
+    required_permissions = "MANAGE_ROLES"
+)]
+pub async fn roles_show(ctx: Context<'_>) -> CommandResult {
+    show(ctx).await
+}
+
+async fn show(ctx: Context<'_>) -> CommandResult {
+    let guild_id = guild_id_of(&ctx)?;
+    let mut rank_roles = db::get_rank_roles(&ctx.data().db_pool, &guild_id.to_string()).await?;
+    if rank_roles.is_empty() {
+        ctx.say("No rank roles, add one with `tetr roles set <rank> <role>`")
+            .await?;
+        return Ok(());
+    }
+    rank_roles.sort_by_key(|r| std::cmp::Reverse(client::rank_index(&r.rank)));
+    ctx.send(|b| {
+        b.embed(|b| {
+            b.title("Rank roles").description(
+                rank_roles
+                    .iter()
+                    .map(|r| format!("**{}** <@&{}>", r.rank.to_uppercase(), r.role_id))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// Give a role to monitored users of a Tetra League rank
+#[poise::command(
+    prefix_command,
+    slash_command,
+    rename = "set",
+    guild_only,
+    required_permissions = "MANAGE_ROLES",
+    required_bot_permissions = "MANAGE_ROLES"
+)]
+pub async fn roles_set(
+    ctx: Context<'_>,
+    #[description = "Rank, e.g. S+"]
+    #[autocomplete = "autocomplete_rank"]
+    rank: String,
+    #[description = "Role to give"] role: serenity::Role,
+) -> CommandResult {
+    let rank = match parse_rank(&rank) {
+        Some(rank) => rank,
+        None => {
+            ctx.say(format!("`{}` is not a Tetra League rank", rank))
+                .await?;
+            return Ok(());
+        }
+    };
+    let guild_id = guild_id_of(&ctx)?;
+    db::set_rank_role(
+        &ctx.data().db_pool,
+        &db::RankRole {
+            guild_id: guild_id.to_string(),
+            rank: rank.into(),
+            role_id: role.id.to_string(),
+        },
+    )
+    .await?;
+    // Also catches members who joined since their rank last changed
+    db::delete_rank_role_syncs(&ctx.data().db_pool, &guild_id.to_string()).await?;
+    let mut reply = format!("{} rank now gives <@&{}>", rank.to_uppercase(), role.id);
+    if unmanageable_roles(&ctx, guild_id).await?.contains(&role.id) {
+        reply.push_str(
+            "\nThe role is above the bot's highest role, move the bot's role up so it can give it",
+        );
+    }
+    ctx.say(reply).await?;
+    Ok(())
+}
+
+/// Stop giving a role for a Tetra League rank
+#[poise::command(
+    prefix_command,
+    slash_command,
+    rename = "unset",
+    guild_only,
+    required_permissions = "MANAGE_ROLES"
+)]
+pub async fn roles_unset(
+    ctx: Context<'_>,
+    #[description = "Rank, e.g. S+"]
+    #[autocomplete = "autocomplete_rank"]
+    rank: String,
+) -> CommandResult {
+    let guild_id = guild_id_of(&ctx)?;
+    let rank = rank.trim().to_lowercase();
+    if db::delete_rank_role(&ctx.data().db_pool, &guild_id.to_string(), &rank).await? {
+        ctx.say(format!(
+            "{} rank no longer gives a role, members keep it until removed by hand",
+            rank.to_uppercase()
+        ))
+        .await?;
+    } else {
+        ctx.say(format!("{} rank has no role", rank.to_uppercase()))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Preview the rank role changes of every linked member
+#[poise::command(
+    prefix_command,
+    slash_command,
+    rename = "preview",
+    guild_only,
+    required_permissions = "MANAGE_ROLES"
+)]
+pub async fn roles_preview(ctx: Context<'_>) -> CommandResult {
+    let guild_id = guild_id_of(&ctx)?;
+    let changes = sync_guild_roles(&ctx.discord().http, ctx.data(), guild_id, true).await?;
+    if changes.is_empty() {
+        ctx.say("Every linked member already has the right rank role")
+            .await?;
+        return Ok(());
+    }
+    let unmanageable = unmanageable_roles(&ctx, guild_id).await?;
+    let lines = changes
+        .iter()
+        .map(|c| {
+            let roles = c
+                .add
+                .iter()
+                .map(|r| (r, "+"))
+                .chain(c.remove.iter().map(|r| (r, "-")))
+                .map(|(r, sign)| {
+                    let warning = if unmanageable.contains(r) {
+                        " ⚠️"
+                    } else {
+                        ""
+                    };
+                    format!("{}<@&{}>{}", sign, r, warning)
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            match c.error.as_deref() {
+                Some(error) => format!("**{}** <@{}> ⚠️ {}", c.username, c.discord_id, error),
+                None => format!("**{}** <@{}> {}", c.username, c.discord_id, roles),
+            }
+        })
+        .collect::<Vec<_>>();
+    let description = join_lines(&lines, MAX_PREVIEW_CHANGES, EMBED_DESCRIPTION_LIMIT);
+    ctx.send(|b| {
+        b.embed(|b| {
+            b.title(format!("{} members would get new roles", changes.len()))
+                .description(&description);
+            if description.contains('⚠') {
+                b.footer(|b| b.text("⚠️ Role is above the bot's highest role and cannot be given"));
+            }
+            b
+        })
+    })
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,304 @@
+use super::{db, db::TrackedPlayer, monitor_users};
+use crate::{CommandResult, Context, Error};
+
+use anyhow::Context as anyhowContext;
+use poise::serenity_prelude::{Attachment, AttachmentType};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const MAX_IMPORT_BYTES: u64 = 1024 * 1024;
+const MAX_IMPORT_USERS: usize = 1000;
+
+const CSV_COLUMNS: [&str; 10] = [
+    "user_id",
+    "username",
+    "country",
+    "league_rank",
+    "league_rating",
+    "personal_best_40l",
+    "personal_best_blitz",
+    "game_time",
+    "games_played",
+    "games_won",
+];
+
+/// A monitor as exported, without anything specific to its channel
+#[derive(Serialize)]
+struct ExportedMonitor<'a> {
+    user_id: &'a str,
+    username: &'a str,
+    country: Option<&'a str>,
+    league_rank: Option<&'a str>,
+    league_rating: Option<f64>,
+    personal_best_40l: Option<i32>,
+    personal_best_blitz: Option<i32>,
+    game_time: f64,
+    games_played: i32,
+    games_won: i32,
+}
+
+impl<'a> From<&'a TrackedPlayer> for ExportedMonitor<'a> {
+    fn from(m: &'a TrackedPlayer) -> Self {
+        ExportedMonitor {
+            user_id: &m.user_id,
+            username: &m.username,
+            country: m.country.as_deref(),
+            league_rank: m.league_rank.as_deref(),
+            league_rating: m.league_rating,
+            personal_best_40l: m.last_personal_best_40l,
+            personal_best_blitz: m.last_personal_best_blitz,
+            game_time: m.game_time,
+            games_played: m.games_played,
+            games_won: m.games_won,
+        }
+    }
+}
+
+impl ExportedMonitor<'_> {
+    fn csv_row(&self) -> String {
+        fn opt<T: ToString>(v: Option<T>) -> String {
+            v.map(|v| v.to_string()).unwrap_or_default()
+        }
+        [
+            self.user_id.to_string(),
+            self.username.to_string(),
+            opt(self.country),
+            opt(self.league_rank),
+            opt(self.league_rating),
+            opt(self.personal_best_40l),
+            opt(self.personal_best_blitz),
+            self.game_time.to_string(),
+            self.games_played.to_string(),
+            self.games_won.to_string(),
+        ]
+        .iter()
+        .map(|f| csv_escape(f))
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+/// A user to import, either a bare username/id or an exported monitor
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ImportedUser {
+    Name(String),
+    Monitor {
+        user_id: Option<String>,
+        username: Option<String>,
+    },
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+/// Splits a CSV line into fields. Quoted fields may contain commas and `""`,
+/// but not line breaks.
+fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn to_csv(monitors: &[TrackedPlayer]) -> String {
+    let mut lines = vec![CSV_COLUMNS.join(",")];
+    lines.extend(monitors.iter().map(|m| ExportedMonitor::from(m).csv_row()));
+    lines.join("\n") + "\n"
+}
+
+/// Users of a CSV file with a `user_id` or `username` column, or of the first
+/// column if there is no header
+fn users_from_csv(contents: &str) -> Vec<String> {
+    let mut rows = contents
+        .lines()
+        .map(|l| l.trim_end_matches('\r'))
+        .filter(|l| !l.trim().is_empty())
+        .map(parse_csv_line)
+        .peekable();
+    let header = rows
+        .peek()
+        .map(|h| {
+            h.iter()
+                .map(|f| f.trim().to_lowercase())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let column = |name| header.iter().position(|f| f == name);
+    let (id_column, name_column) = (column("user_id"), column("username"));
+    if id_column.is_none() && name_column.is_none() {
+        return rows.filter_map(|r| r.into_iter().next()).collect();
+    }
+    rows.skip(1)
+        .filter_map(|r| {
+            let field = |i: Option<usize>| {
+                i.and_then(|i| r.get(i))
+                    .map(|f| f.trim())
+                    .filter(|f| !f.is_empty())
+                    .map(String::from)
+            };
+            field(id_column).or_else(|| field(name_column))
+        })
+        .collect()
+}
+
+fn users_from_json(contents: &str) -> Result<Vec<String>, Error> {
+    let users: Vec<ImportedUser> =
+        serde_json::from_str(contents).context("expected a JSON array of users")?;
+    Ok(users
+        .into_iter()
+        .filter_map(|u| match u {
+            ImportedUser::Name(name) => Some(name),
+            ImportedUser::Monitor { user_id, username } => user_id.or(username),
+        })
+        .collect())
+}
+
+/// Trims and deduplicates users case-insensitively, keeping the first one
+fn dedup_users(users: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    users
+        .into_iter()
+        .map(|u| u.trim().to_string())
+        .filter(|u| !u.is_empty() && seen.insert(u.to_lowercase()))
+        .collect()
+}
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum ExportFormat {
+    #[name = "CSV"]
+    Csv,
+    #[name = "JSON"]
+    Json,
+}
+
+/// Export the tetr.io users monitored in this channel
+#[poise::command(prefix_command, slash_command, guild_cooldown = 5)]
+pub async fn export(
+    ctx: Context<'_>,
+    #[description = "File format, CSV by default"] format: Option<ExportFormat>,
+) -> CommandResult {
+    let monitors = db::get_players_for_channel(&ctx.data().db_pool, ctx.channel_id().to_string())
+        .await
+        .context("failed to get monitored users from db")?;
+    if monitors.is_empty() {
+        ctx.say("No monitored users").await?;
+        return Ok(());
+    }
+    let (contents, extension) = match format.unwrap_or(ExportFormat::Csv) {
+        ExportFormat::Csv => (to_csv(&monitors), "csv"),
+        ExportFormat::Json => (
+            serde_json::to_string_pretty(
+                &monitors
+                    .iter()
+                    .map(ExportedMonitor::from)
+                    .collect::<Vec<_>>(),
+            )?,
+            "json",
+        ),
+    };
+    ctx.send(|b| {
+        b.content(format!("Exported {} monitored users", monitors.len()))
+            .attachment(AttachmentType::Bytes {
+                data: contents.into_bytes().into(),
+                filename: format!("monitors-{}.{}", ctx.channel_id(), extension),
+            })
+    })
+    .await?;
+    Ok(())
+}
+
+/// Monitor every tetr.io user of an exported CSV or JSON file
+#[poise::command(prefix_command, slash_command, owners_only, guild_cooldown = 5)]
+pub async fn import(
+    ctx: Context<'_>,
+    #[description = "CSV or JSON file from tetr export"] file: Attachment,
+) -> CommandResult {
+    if file.size > MAX_IMPORT_BYTES {
+        ctx.say(format!(
+            "File is too big, the limit is {}KiB",
+            MAX_IMPORT_BYTES / 1024
+        ))
+        .await?;
+        return Ok(());
+    }
+    let bytes = file.download().await.context("failed to download file")?;
+    let contents = String::from_utf8(bytes).context("file is not valid UTF-8")?;
+    let is_json =
+        file.filename.to_lowercase().ends_with(".json") || contents.trim_start().starts_with('[');
+    let users = dedup_users(if is_json {
+        users_from_json(&contents)?
+    } else {
+        users_from_csv(&contents)
+    });
+
+    if users.is_empty() {
+        ctx.say("No users found in the file").await?;
+        return Ok(());
+    }
+    if users.len() > MAX_IMPORT_USERS {
+        ctx.say(format!(
+            "Too many users ({}), at most {} can be imported at once",
+            users.len(),
+            MAX_IMPORT_USERS
+        ))
+        .await?;
+        return Ok(());
+    }
+    monitor_users(&ctx, users).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_fields() {
+        assert_eq!(
+            parse_csv_line(r#"a,"b,c","say ""hi""",,"#),
+            vec!["a", "b,c", "say \"hi\"", "", ""]
+        );
+        assert_eq!(parse_csv_line(&csv_escape("x,\"y\"")), vec!["x,\"y\""]);
+    }
+
+    #[test]
+    fn reads_users_from_csv() {
+        let csv = "username,user_id\r\nosk,5e32fc85ab319c2ab1beb07c\r\nczsmall0402,\n\n";
+        assert_eq!(
+            users_from_csv(csv),
+            vec!["5e32fc85ab319c2ab1beb07c", "czsmall0402"]
+        );
+        assert_eq!(users_from_csv("osk\nZUDO\n"), vec!["osk", "ZUDO"]);
+    }
+
+    #[test]
+    fn reads_users_from_json() {
+        let json = r#"["osk", {"username": "zudo"}, {"user_id": "5e32fc85ab319c2ab1beb07c", "username": "x"}]"#;
+        assert_eq!(
+            users_from_json(json).unwrap(),
+            vec!["osk", "zudo", "5e32fc85ab319c2ab1beb07c"]
+        );
+        assert_eq!(
+            dedup_users(vec![" osk".into(), "OSK".into(), "".into()]),
+            vec!["osk"]
+        );
+    }
+}
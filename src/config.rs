@@ -0,0 +1,285 @@
+use serde_derive::Deserialize;
+use std::{fmt::Display, path::Path, str::FromStr, time::Duration};
+
+static DEFAULT_CONFIG_PATH: &str = "config.toml";
+static CONFIG_PATH_VAR: &str = "VIERIBOT_CONFIG";
+static ENV_PREFIX: &str = "VIERIBOT_";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("cannot read config file {0}: {1}")]
+    Read(String, #[source] std::io::Error),
+    #[error("cannot parse config file {0}: {1}")]
+    Parse(String, #[source] toml::de::Error),
+    #[error("invalid value `{value}` for {var}: {reason}")]
+    Env {
+        var: String,
+        value: String,
+        reason: String,
+    },
+    #[error("invalid config: {0}")]
+    Invalid(String),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Default prefix for prefix commands
+    pub prefix: String,
+    pub database: DatabaseConfig,
+    pub tetr: TetrConfig,
+    pub snapshots: SnapshotConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub max_connections: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TetrConfig {
+    pub api_base_url: String,
+    pub session_id: String,
+    /// Maximum number of tetr.io requests in flight for a single command or poll
+    pub max_concurrent_requests: usize,
+    /// Seconds between two polls of every tracked player
+    pub poll_interval_secs: u64,
+    /// Minimum TR change before it is announced
+    pub tr_change_threshold: f64,
+    /// Hours of play time between two announced playtime milestones
+    pub playtime_milestone_hours: u64,
+    /// Whether to announce in monitoring channels when a user renames
+    pub announce_renames: bool,
+    /// Seconds between two checks for monitoring channels the bot cannot post to
+    pub sweep_interval_secs: u64,
+    /// Sustained rate of tetr.io requests shared by every caller
+    pub requests_per_sec: f64,
+    /// Number of requests that may be sent at once after being idle
+    pub burst: u32,
+    /// Retries of a request failing with 429, 5xx or a timeout
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every further retry. A 429
+    /// asking to wait longer than the last retry would is not retried.
+    pub retry_base_delay_ms: u64,
+    pub request_timeout_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// Days for which every snapshot is kept before being downsampled to one a day
+    pub full_resolution_days: i32,
+    /// Days after which snapshots are deleted, 0 to keep them forever
+    pub retention_days: i32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            prefix: "!#".into(),
+            database: DatabaseConfig::default(),
+            tetr: TetrConfig::default(),
+            snapshots: SnapshotConfig::default(),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig { max_connections: 4 }
+    }
+}
+
+impl Default for TetrConfig {
+    fn default() -> Self {
+        TetrConfig {
+            api_base_url: "https://ch.tetr.io/api/".into(),
+            session_id: "aiweufhisdhf".into(),
+            max_concurrent_requests: 64,
+            poll_interval_secs: 5 * 60,
+            tr_change_threshold: 100.0,
+            playtime_milestone_hours: 100,
+            announce_renames: true,
+            sweep_interval_secs: 6 * 60 * 60,
+            requests_per_sec: 5.0,
+            burst: 10,
+            max_retries: 3,
+            retry_base_delay_ms: 500,
+            request_timeout_secs: 10,
+        }
+    }
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            full_resolution_days: 7,
+            retention_days: 365,
+        }
+    }
+}
+
+impl TetrConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs)
+    }
+
+    pub fn sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.sweep_interval_secs)
+    }
+
+    pub fn retry_base_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_base_delay_ms)
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs)
+    }
+}
+
+fn override_from_env<T>(field: &mut T, name: &str) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let var = format!("{}{}", ENV_PREFIX, name);
+    if let Ok(value) = std::env::var(&var) {
+        *field = value.parse().map_err(|e: T::Err| ConfigError::Env {
+            var,
+            value,
+            reason: e.to_string(),
+        })?;
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Loads the config file pointed to by `VIERIBOT_CONFIG` (or `config.toml`
+    /// if it exists), then applies `VIERIBOT_*` environment overrides.
+    pub fn load() -> Result<Config, ConfigError> {
+        let mut config = match std::env::var(CONFIG_PATH_VAR) {
+            Ok(path) => Config::from_file(&path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Config::from_file(DEFAULT_CONFIG_PATH)?
+            }
+            Err(_) => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Config, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.into(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.into(), e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        override_from_env(&mut self.prefix, "PREFIX")?;
+        override_from_env(
+            &mut self.database.max_connections,
+            "DATABASE_MAX_CONNECTIONS",
+        )?;
+        override_from_env(&mut self.tetr.api_base_url, "TETR_API_BASE_URL")?;
+        override_from_env(&mut self.tetr.session_id, "TETR_SESSION_ID")?;
+        override_from_env(
+            &mut self.tetr.max_concurrent_requests,
+            "TETR_MAX_CONCURRENT_REQUESTS",
+        )?;
+        override_from_env(&mut self.tetr.poll_interval_secs, "TETR_POLL_INTERVAL_SECS")?;
+        override_from_env(
+            &mut self.tetr.tr_change_threshold,
+            "TETR_TR_CHANGE_THRESHOLD",
+        )?;
+        override_from_env(
+            &mut self.tetr.playtime_milestone_hours,
+            "TETR_PLAYTIME_MILESTONE_HOURS",
+        )?;
+        override_from_env(&mut self.tetr.announce_renames, "TETR_ANNOUNCE_RENAMES")?;
+        override_from_env(
+            &mut self.tetr.sweep_interval_secs,
+            "TETR_SWEEP_INTERVAL_SECS",
+        )?;
+        override_from_env(&mut self.tetr.requests_per_sec, "TETR_REQUESTS_PER_SEC")?;
+        override_from_env(&mut self.tetr.burst, "TETR_BURST")?;
+        override_from_env(&mut self.tetr.max_retries, "TETR_MAX_RETRIES")?;
+        override_from_env(
+            &mut self.tetr.retry_base_delay_ms,
+            "TETR_RETRY_BASE_DELAY_MS",
+        )?;
+        override_from_env(
+            &mut self.tetr.request_timeout_secs,
+            "TETR_REQUEST_TIMEOUT_SECS",
+        )?;
+        override_from_env(
+            &mut self.snapshots.full_resolution_days,
+            "SNAPSHOTS_FULL_RESOLUTION_DAYS",
+        )?;
+        override_from_env(
+            &mut self.snapshots.retention_days,
+            "SNAPSHOTS_RETENTION_DAYS",
+        )?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: &str| Err(ConfigError::Invalid(msg.into()));
+        if self.prefix.trim().is_empty() {
+            return invalid("prefix must not be empty");
+        }
+        if self.database.max_connections == 0 {
+            return invalid("database.max_connections must be at least 1");
+        }
+        match reqwest::Url::parse(&self.tetr.api_base_url) {
+            Ok(url) if url.path().ends_with('/') => {}
+            Ok(_) => return invalid("tetr.api_base_url must end with `/`"),
+            Err(e) => {
+                return Err(ConfigError::Invalid(format!(
+                    "tetr.api_base_url is not a valid URL: {}",
+                    e
+                )))
+            }
+        }
+        if self.tetr.session_id.is_empty() {
+            return invalid("tetr.session_id must not be empty");
+        }
+        if self.tetr.max_concurrent_requests == 0 {
+            return invalid("tetr.max_concurrent_requests must be at least 1");
+        }
+        if self.tetr.poll_interval_secs == 0 {
+            return invalid("tetr.poll_interval_secs must be at least 1");
+        }
+        if self.tetr.sweep_interval_secs == 0 {
+            return invalid("tetr.sweep_interval_secs must be at least 1");
+        }
+        if self.tetr.tr_change_threshold.is_nan() || self.tetr.tr_change_threshold <= 0.0 {
+            return invalid("tetr.tr_change_threshold must be positive");
+        }
+        if self.tetr.playtime_milestone_hours == 0 {
+            return invalid("tetr.playtime_milestone_hours must be at least 1");
+        }
+        if self.tetr.requests_per_sec.is_nan() || self.tetr.requests_per_sec <= 0.0 {
+            return invalid("tetr.requests_per_sec must be positive");
+        }
+        if self.tetr.burst == 0 {
+            return invalid("tetr.burst must be at least 1");
+        }
+        if self.tetr.request_timeout_secs == 0 {
+            return invalid("tetr.request_timeout_secs must be at least 1");
+        }
+        if self.snapshots.full_resolution_days < 0 || self.snapshots.retention_days < 0 {
+            return invalid("snapshots.* must not be negative");
+        }
+        if self.snapshots.retention_days != 0
+            && self.snapshots.retention_days < self.snapshots.full_resolution_days
+        {
+            return invalid(
+                "snapshots.retention_days must not be less than snapshots.full_resolution_days",
+            );
+        }
+        Ok(())
+    }
+}
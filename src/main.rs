@@ -1,18 +1,31 @@
 mod commands;
+mod config;
 mod error;
 
+use config::Config;
 use poise::serenity_prelude as serenity;
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use sqlx::{
+    migrate::{MigrateError, Migrator},
+    postgres::PgPoolOptions,
+    Pool, Postgres,
+};
+use std::sync::Arc;
 
 type Error = anyhow::Error;
 type Context<'a> = poise::Context<'a, Data, Error>;
 type CommandResult<E = Error> = Result<(), E>;
 type DbPool = Pool<Postgres>;
 
+static MIGRATOR: Migrator = sqlx::migrate!();
+
 // User data, which is stored and accessible in all command invocations
+#[derive(Clone)]
 pub struct Data {
-    pub reqwest: reqwest::Client,
+    pub tetr: commands::tetr::TetrClient,
     pub db_pool: DbPool,
+    pub config: Arc<Config>,
+    pub prefix_cache: commands::prefix::PrefixCache,
+    pub refresh_locks: commands::tetr::RefreshLocks,
 }
 
 /// Show this help menu
@@ -69,9 +82,9 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
     }
 }
 
-async fn init_db() -> DbPool {
+async fn init_db(config: &Config) -> DbPool {
     match PgPoolOptions::new()
-        .max_connections(4)
+        .max_connections(config.database.max_connections)
         .connect(&std::env::var("DATABASE_URL").expect("missing DATABASE_URL"))
         .await
     {
@@ -82,11 +95,43 @@ async fn init_db() -> DbPool {
     }
 }
 
+async fn migrate_db(pool: &DbPool) {
+    match MIGRATOR.run(pool).await {
+        Ok(()) => println!("Database schema is up to date"),
+        Err(MigrateError::Dirty(version)) => panic!(
+            "Database is dirty at migration {}, fix it manually before starting",
+            version
+        ),
+        Err(MigrateError::VersionMissing(version)) => panic!(
+            "Database has unknown migration {} applied, refusing to start",
+            version
+        ),
+        Err(why) => panic!("Cannot migrate db: {:?}", why),
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(why) => panic!("Cannot load config: {}", why),
+    };
+    let db_pool = init_db(&config).await;
+    migrate_db(&db_pool).await;
+    if std::env::args().any(|a| a == "--migrate-only") {
+        return;
+    }
+
+    let tetr_transport = match commands::tetr::HttpTransport::new(&config.tetr) {
+        Ok(transport) => transport,
+        Err(why) => panic!("Cannot create tetr.io client: {:?}", why),
+    };
     let data = Data {
-        reqwest: reqwest::Client::new(),
-        db_pool: init_db().await,
+        tetr: commands::tetr::TetrClient::new(tetr_transport, &config.tetr),
+        db_pool,
+        config: Arc::new(config),
+        prefix_cache: Default::default(),
+        refresh_locks: Default::default(),
     };
 
     poise::Framework::builder()
@@ -98,14 +143,20 @@ async fn main() {
                 commands::admin::spam(),
                 commands::admin::ping(),
                 commands::admin::dblatency(),
+                commands::admin::tetrcache(),
+                commands::prefix::prefix(),
                 commands::tetr::tetr(),
+                commands::tetr::inactive(),
             ],
             prefix_options: poise::PrefixFrameworkOptions {
-                prefix: Some("!#".into()),
+                dynamic_prefix: Some(|ctx| Box::pin(commands::prefix::dynamic_prefix(ctx))),
                 ..Default::default()
             },
-            /// The global error handler for all error cases that may occur
+            // The global error handler for all error cases that may occur
             on_error: |error| Box::pin(on_error(error)),
+            listener: |_ctx, event, _framework, data| {
+                Box::pin(commands::tetr::on_event(event, data))
+            },
             ..Default::default()
         })
         .token(std::env::var("DISCORD_TOKEN").expect("missing DISCORD_TOKEN"))
@@ -118,6 +169,9 @@ async fn main() {
                 ready.user.name,
                 ready.guilds.len()
             );
+            commands::tetr::spawn_poller(ctx.http.clone(), data.clone());
+            commands::tetr::spawn_sweeper(ctx.http.clone(), data.clone());
+            commands::tetr::spawn_snapshot_pruner(data.clone());
             Box::pin(async {
                 serenity::Command::set_global_application_commands(&ctx.http, |c| {
                     *c =